@@ -0,0 +1,54 @@
+// Scans generated sources of doubling size and reports throughput. With a
+// linear scanner the time per byte stays flat as the input grows.
+//
+//     cargo bench --bench scanner
+
+#[allow(dead_code)]
+#[path = "../src/scanner.rs"]
+mod scanner;
+#[allow(dead_code)]
+#[path = "../src/token.rs"]
+mod token;
+// The module's unit tests are compiled out here, leaving their imports unused.
+#[allow(unused_imports)]
+#[path = "../src/xid.rs"]
+mod xid;
+
+use scanner::Scanner;
+use std::time::Instant;
+use token::TokenType;
+
+const SNIPPET: &str = "var total = (count + 12) * 3 >= limit; // running total\n\
+                       print \"label: \" + name;\n";
+
+fn scan_all(source: String) -> usize {
+    let mut scanner = Scanner::new(source);
+    let mut tokens = 0;
+    loop {
+        let token = scanner.scan_token();
+        tokens += 1;
+        if token.token_type == TokenType::EOF {
+            return tokens;
+        }
+    }
+}
+
+fn main() {
+    let mut baseline = None;
+    for megabytes in [1, 2, 4, 8] {
+        let size = megabytes * 1024 * 1024;
+        let source = SNIPPET.repeat(size / SNIPPET.len());
+        let bytes = source.len();
+
+        let start = Instant::now();
+        let tokens = scan_all(source);
+        let elapsed = start.elapsed();
+
+        let ns_per_byte = elapsed.as_nanos() as f64 / bytes as f64;
+        let relative = ns_per_byte / *baseline.get_or_insert(ns_per_byte);
+        println!(
+            "{:>2} MiB  {:>9} tokens  {:>8.2?}  {:>6.2} ns/byte  {:.2}x per-byte cost vs 1 MiB",
+            megabytes, tokens, elapsed, ns_per_byte, relative
+        );
+    }
+}
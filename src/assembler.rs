@@ -0,0 +1,536 @@
+use crate::bigint::BigInt;
+use crate::chunk::*;
+use crate::value::*;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::iter::Peekable;
+
+// Reads a textual listing back into a chunk. The format is a superset of
+// what `Chunk::disassemble` prints, so a listing round-trips unchanged:
+//
+//   == name ==                  header, ignored
+//   0000    1 OpConstant 0 '1'  offset and line columns are optional
+//   .line 3                     line number for the instructions that follow
+//   .const "hello"              append a constant to the pool
+//   loop:                       label for the current offset
+//   OpConstant "hello"          a literal operand is interned into the pool
+//   OpLoop loop                 jumps name a label or an absolute offset
+//   .handler try end catch 0    errors raised from `try` up to `end` jump to
+//                               `catch`, keeping 0 stack values
+//   ; comment                   comments run to the end of the line
+#[derive(Debug)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[line {}] {}", self.line, self.message)
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Chunk, AssembleError> {
+    let mut assembler = Assembler {
+        chunk: Chunk::new(),
+        constants: Vec::new(),
+        labels: HashMap::new(),
+        jumps: Vec::new(),
+        handlers: Vec::new(),
+        line: 1,
+        source_line: 0,
+    };
+
+    for (index, text) in source.lines().enumerate() {
+        assembler.source_line = index + 1;
+        assembler
+            .line(text)
+            .map_err(|message| AssembleError { line: index + 1, message })?;
+    }
+    assembler.patch_jumps()?;
+    assembler.resolve_handlers()?;
+
+    assembler.finish().map_err(|message| AssembleError {
+        line: source.lines().count(),
+        message,
+    })
+}
+
+struct Assembler {
+    chunk: Chunk,
+    constants: Vec<Option<Value>>,
+    labels: HashMap<String, usize>,
+    // Jump operands to fill in once every label is known.
+    jumps: Vec<Jump>,
+    // Handlers whose offsets may name labels defined later.
+    handlers: Vec<PendingHandler>,
+    line: usize,
+    source_line: usize,
+}
+
+struct Jump {
+    // Offset of the two operand bytes.
+    at: usize,
+    // Offset the distance is measured from, the end of the instruction.
+    from: usize,
+    backward: bool,
+    target: String,
+    // Source line, for errors.
+    line: usize,
+}
+
+struct PendingHandler {
+    // Start, end and target, each a label or an absolute offset.
+    offsets: [String; 3],
+    depth: usize,
+    // Source line, for errors.
+    line: usize,
+}
+
+impl Assembler {
+    fn line(&mut self, text: &str) -> Result<(), String> {
+        if text.trim_start().starts_with("==") {
+            return Ok(());
+        }
+
+        let tokens = tokenize(text)?;
+        let mut tokens = tokens.iter().map(String::as_str).peekable();
+        let Some(&first) = tokens.peek() else {
+            return Ok(());
+        };
+
+        match first {
+            ".line" => {
+                tokens.next();
+                self.line = parse_number(tokens.next(), "line number")?;
+                return expect_end(tokens);
+            }
+            ".const" => {
+                tokens.next();
+                let literal = tokens.next().ok_or("Expect a literal after '.const'.")?;
+                let value = parse_literal(literal)?;
+                self.constants.push(Some(value));
+                return expect_end(tokens);
+            }
+            ".handler" => {
+                tokens.next();
+                let mut offset = || tokens.next().map(str::to_string).ok_or("Expect an offset.");
+                let offsets = [offset()?, offset()?, offset()?];
+                let depth = parse_number(tokens.next(), "handler depth")?;
+                self.handlers.push(PendingHandler {
+                    offsets,
+                    depth,
+                    line: self.source_line,
+                });
+                return expect_end(tokens);
+            }
+            label if label.ends_with(':') => {
+                let name = &label[..label.len() - 1];
+                if self.labels.insert(name.to_string(), self.chunk.code.len()).is_some() {
+                    return Err(format!("Label '{}' is already defined.", name));
+                }
+                tokens.next();
+                return expect_end(tokens);
+            }
+            _ => {}
+        }
+
+        // Listing columns: a byte offset, then a line number or '|'.
+        if first.bytes().all(|b| b.is_ascii_digit()) {
+            let offset: usize = parse_number(tokens.next(), "offset")?;
+            if offset != self.chunk.code.len() {
+                return Err(format!(
+                    "Listing offset {:04} does not match assembled offset {:04}.",
+                    offset,
+                    self.chunk.code.len()
+                ));
+            }
+            match tokens.next() {
+                Some("|") => {}
+                line => self.line = parse_number(line, "line number")?,
+            }
+        }
+
+        let mnemonic = tokens.next().ok_or("Expect an instruction.")?;
+        let opcode = opcode_named(mnemonic).ok_or(format!("Unknown instruction '{}'.", mnemonic))?;
+        let end = self.chunk.code.len() + opcode.width();
+        self.chunk.write(opcode.into(), self.line);
+
+        // With several operands, the disassembler prints the constant's
+        // literal after all of them.
+        let mut annotated = None;
+        for operand in opcode.info().operands {
+            let byte = match operand {
+                Operand::Constant | Operand::Name => {
+                    let (byte, index) = self.constant_operand(&mut tokens)?;
+                    annotated = index;
+                    byte
+                }
+                Operand::Count => parse_number(tokens.next(), "count")?,
+                Operand::Slot => parse_number(tokens.next(), "slot")?,
+                Operand::Jump | Operand::Loop => {
+                    let target = tokens.next().ok_or("Expect a jump target.")?;
+                    self.jumps.push(Jump {
+                        at: self.chunk.code.len(),
+                        from: end,
+                        backward: *operand == Operand::Loop,
+                        target: target.to_string(),
+                        line: self.source_line,
+                    });
+                    self.chunk.write(0, self.line);
+                    0
+                }
+            };
+            self.chunk.write(byte, self.line);
+        }
+        if let (Some(index), Some(annotation)) = (annotated, tokens.peek()) {
+            if annotation.starts_with('\'') {
+                self.annotate(index, annotation)?;
+                tokens.next();
+            }
+        }
+        expect_end(tokens)
+    }
+
+    // `N`, `N 'literal'` (as printed by the disassembler) or a bare literal.
+    // Also returns the index of an `N` that has not been annotated yet.
+    fn constant_operand<'t>(
+        &mut self,
+        tokens: &mut Peekable<impl Iterator<Item = &'t str>>,
+    ) -> Result<(u8, Option<usize>), String> {
+        let operand = tokens.next().ok_or("Expect a constant operand.")?;
+
+        if !operand.bytes().all(|b| b.is_ascii_digit()) {
+            let value = parse_literal(operand.trim_matches('\''))?;
+            return Ok((self.intern(value)?, None));
+        }
+
+        let index: usize = parse_number(Some(operand), "constant index")?;
+        if index >= self.constants.len() {
+            self.constants.resize(index + 1, None);
+        }
+        let byte =
+            u8::try_from(index).map_err(|_| format!("Constant index {} is too large.", index))?;
+        match tokens.next_if(|token| token.starts_with('\'')) {
+            Some(annotation) => {
+                self.annotate(index, annotation)?;
+                Ok((byte, None))
+            }
+            None => Ok((byte, Some(index))),
+        }
+    }
+
+    fn annotate(&mut self, index: usize, annotation: &str) -> Result<(), String> {
+        let value = parse_literal(annotation.trim_matches('\''))?;
+        match &self.constants[index] {
+            Some(existing) if existing.literal() != value.literal() => Err(format!(
+                "Constant {} is already defined as {}.",
+                index,
+                existing.literal()
+            )),
+            _ => {
+                self.constants[index] = Some(value);
+                Ok(())
+            }
+        }
+    }
+
+    fn intern(&mut self, value: Value) -> Result<u8, String> {
+        let literal = value.literal();
+        let index = match self
+            .constants
+            .iter()
+            .position(|c| c.as_ref().is_some_and(|c| c.literal() == literal))
+        {
+            Some(index) => index,
+            None => {
+                self.constants.push(Some(value));
+                self.constants.len() - 1
+            }
+        };
+        u8::try_from(index).map_err(|_| "Too many constants in one chunk.".to_string())
+    }
+
+    fn patch_jumps(&mut self) -> Result<(), AssembleError> {
+        for jump in &self.jumps {
+            let error = |message| AssembleError {
+                line: jump.line,
+                message,
+            };
+            let target = self.offset(&jump.target).map_err(error)?;
+            let distance = match jump.backward {
+                false if target >= jump.from => target - jump.from,
+                true if target <= jump.from => jump.from - target,
+                false => return Err(error("A forward jump cannot lead backwards.".to_string())),
+                true => return Err(error("OpLoop cannot lead forwards.".to_string())),
+            };
+            let distance =
+                u16::try_from(distance).map_err(|_| error("Jump is too far.".to_string()))?;
+            self.chunk.code[jump.at..jump.at + 2].copy_from_slice(&distance.to_be_bytes());
+        }
+        Ok(())
+    }
+
+    fn resolve_handlers(&mut self) -> Result<(), AssembleError> {
+        for pending in &self.handlers {
+            let error = |message| AssembleError {
+                line: pending.line,
+                message,
+            };
+            let [start, end, target] = &pending.offsets;
+            self.chunk.handlers.push(Handler {
+                start: self.offset(start).map_err(error)?,
+                end: self.offset(end).map_err(error)?,
+                target: self.offset(target).map_err(error)?,
+                depth: pending.depth,
+            });
+        }
+        Ok(())
+    }
+
+    // A label's offset, or an absolute offset as written.
+    fn offset(&self, name: &str) -> Result<usize, String> {
+        match self.labels.get(name) {
+            Some(&offset) => Ok(offset),
+            None if name.bytes().all(|b| b.is_ascii_digit()) => parse_number(Some(name), "offset"),
+            None => Err(format!("Undefined label '{}'.", name)),
+        }
+    }
+
+    fn finish(mut self) -> Result<Chunk, String> {
+        for (index, constant) in self.constants.into_iter().enumerate() {
+            match constant {
+                Some(value) => self.chunk.constants.write(value),
+                None => return Err(format!("Constant {} is used but never defined.", index)),
+            };
+        }
+        Ok(self.chunk)
+    }
+}
+
+fn opcode_named(name: &str) -> Option<OpCode> {
+    OpCode::all().iter().copied().find(|opcode| opcode.name() == name)
+}
+
+fn expect_end<'t>(mut tokens: impl Iterator<Item = &'t str>) -> Result<(), String> {
+    match tokens.next() {
+        Some(token) => Err(format!("Unexpected '{}'.", token)),
+        None => Ok(()),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(token: Option<&str>, what: &str) -> Result<T, String> {
+    let token = token.ok_or(format!("Expect {}.", what))?;
+    token
+        .parse()
+        .map_err(|_| format!("Invalid {} '{}'.", what, token))
+}
+
+// Splits a line into words, double-quoted strings and a trailing
+// single-quoted annotation, dropping any comment.
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            ';' => break,
+            '\'' => {
+                let annotation = text[start..].trim_end();
+                if annotation.len() < 2 || !annotation.ends_with('\'') {
+                    return Err("Unterminated constant annotation.".to_string());
+                }
+                tokens.push(annotation.to_string());
+                break;
+            }
+            '"' => {
+                chars.next();
+                let mut escaped = false;
+                let mut end = None;
+                for (i, c) in chars.by_ref() {
+                    match c {
+                        _ if escaped => escaped = false,
+                        '\\' => escaped = true,
+                        '"' => {
+                            end = Some(i + 1);
+                            break;
+                        }
+                        _ => {}
+                    }
+                }
+                let end = end.ok_or("Unterminated string.")?;
+                tokens.push(text[start..end].to_string());
+            }
+            _ => {
+                let mut end = text.len();
+                while let Some(&(i, c)) = chars.peek() {
+                    if c.is_whitespace() || c == ';' {
+                        end = i;
+                        break;
+                    }
+                    chars.next();
+                }
+                tokens.push(text[start..end].to_string());
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse_literal(text: &str) -> Result<Value, String> {
+    match text {
+        "nil" => return Ok(Value::Nil),
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+
+    if let Some(body) = text.strip_prefix('"') {
+        let body = body
+            .strip_suffix('"')
+            .ok_or(format!("Unterminated string {}.", text))?;
+        return unescape(body).map(Value::Str);
+    }
+
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    if let Some(n) = BigInt::parse(digits, 10) {
+        return Ok(Value::integer(if negative { -&n } else { n }));
+    }
+    text.parse::<f64>()
+        .map(Value::Number)
+        .map_err(|_| format!("Invalid literal '{}'.", text))
+}
+
+// Accepts the escapes produced by `Value::literal`.
+fn unescape(body: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = body.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            Some('\'') => out.push('\''),
+            Some('u') => {
+                let rest: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let hex = rest
+                    .strip_prefix('{')
+                    .ok_or("Expect '{' after '\\u'.")?;
+                let code = u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(format!("Invalid unicode escape '\\u{{{}}}'.", hex))?;
+                out.push(code);
+            }
+            Some(other) => return Err(format!("Invalid escape '\\{}'.", other)),
+            None => return Err("Unterminated escape.".to_string()),
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn compile(source: &str) -> Chunk {
+        let mut chunk = Chunk::new();
+        assert!(!Compiler::new(&mut chunk).compile(source.to_string()));
+        chunk
+    }
+
+    fn assert_same(a: &Chunk, b: &Chunk) {
+        assert_eq!(a.code, b.code);
+        assert_eq!(a.lines, b.lines);
+        assert_eq!(a.handlers, b.handlers);
+        let literals = |chunk: &Chunk| -> Vec<String> {
+            chunk.constants.values.iter().map(Value::literal).collect()
+        };
+        assert_eq!(literals(a), literals(b));
+    }
+
+    #[test]
+    fn round_trips_disassembly() {
+        let chunk = compile(
+            r#"
+            var s = "quote \" and 'apostrophe'\n";
+            var xs = [1, -2.0, 1e300, 99999999999999999999, nil];
+            for i in 0..=2 {
+                try {
+                    print xs[i] // i;
+                } catch (e) {
+                    print e.kind();
+                    continue;
+                } finally {
+                    print s;
+                }
+            }
+            "#,
+        );
+        let listing = chunk.listing("test");
+        let assembled = assemble(&listing).unwrap();
+        assert_same(&chunk, &assembled);
+        assert_eq!(assembled.listing("test"), listing);
+    }
+
+    #[test]
+    fn assembles_labels_and_handlers() {
+        let chunk = assemble(
+            r#"
+            .line 1
+            start:
+            OpConstant "boom"
+            OpThrow
+            end:
+            catch:
+            OpPrint
+            OpReturn
+            .handler start end catch 0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(chunk.code.len(), 5);
+        assert_eq!(
+            chunk.handlers,
+            vec![Handler {
+                start: 0,
+                end: 3,
+                target: 3,
+                depth: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_mismatched_listing_offset() {
+        let Err(err) = assemble("0000    1 OpNil\n0002    | OpReturn\n") else {
+            panic!("assembled a listing with a wrong offset");
+        };
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn rejects_undefined_label() {
+        let Err(err) = assemble("OpJump nowhere\nOpReturn\n") else {
+            panic!("assembled a jump to an undefined label");
+        };
+        assert_eq!(err.message, "Undefined label 'nowhere'.");
+    }
+}
@@ -0,0 +1,707 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use std::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Rem, Shl, Shr, Sub};
+
+// An arbitrary-precision integer, stored as a sign and a magnitude. The
+// magnitude is a list of base 2^32 limbs, least significant first, with no
+// high zero limbs, so zero is the empty list and is never negative.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let negative = negative && !limbs.is_empty();
+        Self { negative, limbs }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    // Parses unsigned digits in the given radix, or None if any is invalid.
+    pub fn parse(digits: &str, radix: u32) -> Option<Self> {
+        if digits.is_empty() {
+            return None;
+        }
+        let mut limbs = Vec::new();
+        for c in digits.chars() {
+            let digit = c.to_digit(radix)?;
+            mul_small_add(&mut limbs, radix, digit);
+        }
+        Some(Self::new(false, limbs))
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| acc << 32 | limb as u64);
+        if self.negative {
+            0i64.checked_sub_unsigned(magnitude)
+        } else {
+            i64::try_from(magnitude).ok()
+        }
+    }
+
+    // Rounds to the nearest float, or an infinity if out of range.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self
+            .limbs
+            .iter()
+            .rev()
+            .fold(0.0, |acc, &limb| acc * 4_294_967_296.0 + limb as f64);
+        if self.negative {
+            -magnitude
+        } else {
+            magnitude
+        }
+    }
+
+    // The exact value of a finite float with no fractional part.
+    pub fn from_f64(n: f64) -> Option<Self> {
+        if !n.is_finite() || n.fract() != 0.0 {
+            return None;
+        }
+        let bits = n.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64 - 1075;
+        let mantissa = (bits & ((1 << 52) - 1)) | 1 << 52;
+        let magnitude = if exponent >= 0 {
+            shift_left(&from_u64(mantissa), exponent as usize)
+        } else {
+            from_u64(mantissa >> -exponent.max(-63))
+        };
+        Some(Self::new(n < 0.0, magnitude))
+    }
+
+    // Quotient and remainder, with the quotient truncated toward zero and
+    // the remainder taking the sign of the dividend. Panics on a zero
+    // divisor, which callers reject first.
+    pub fn div_rem(&self, other: &BigInt) -> (BigInt, BigInt) {
+        assert!(!other.is_zero(), "BigInt division by zero");
+        let (quotient, remainder) = divide(&self.limbs, &other.limbs);
+        (
+            Self::new(self.negative != other.negative, quotient),
+            Self::new(self.negative, remainder),
+        )
+    }
+
+    // Quotient rounded toward negative infinity, and a remainder with the
+    // sign of the divisor, so that `a == q * b + r` still holds.
+    pub fn div_rem_floor(&self, other: &BigInt) -> (BigInt, BigInt) {
+        let (quotient, remainder) = self.div_rem(other);
+        if !remainder.is_zero() && remainder.negative != other.negative {
+            (&quotient - &BigInt::from(1), &remainder + other)
+        } else {
+            (quotient, remainder)
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    // Number of bits in the magnitude.
+    pub fn bits(&self) -> u64 {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() as u64 * 32 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn pow(&self, mut exponent: u32) -> BigInt {
+        let mut base = self.clone();
+        let mut result = BigInt::from(1);
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                result = &result * &base;
+            }
+            exponent >>= 1;
+            if exponent > 0 {
+                base = &base * &base;
+            }
+        }
+        result
+    }
+}
+
+impl From<i64> for BigInt {
+    fn from(n: i64) -> Self {
+        Self::new(n < 0, from_u64(n.unsigned_abs()))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare(&self.limbs, &other.limbs),
+            (true, true) => compare(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: Self) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::new(self.negative, add(&self.limbs, &other.limbs));
+        }
+        match compare(&self.limbs, &other.limbs) {
+            Ordering::Less => BigInt::new(other.negative, sub(&other.limbs, &self.limbs)),
+            _ => BigInt::new(self.negative, sub(&self.limbs, &other.limbs)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: Self) -> BigInt {
+        self + &-other
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: Self) -> BigInt {
+        BigInt::new(self.negative != other.negative, mul(&self.limbs, &other.limbs))
+    }
+}
+
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    fn div(self, other: Self) -> BigInt {
+        self.div_rem(other).0
+    }
+}
+
+impl Rem for &BigInt {
+    type Output = BigInt;
+
+    fn rem(self, other: Self) -> BigInt {
+        self.div_rem(other).1
+    }
+}
+
+// The bitwise operators treat negative numbers as if in two's complement
+// with infinitely many leading one bits, so `-1 & x == x`.
+impl BitAnd for &BigInt {
+    type Output = BigInt;
+
+    fn bitand(self, other: Self) -> BigInt {
+        bitwise(self, other, |a, b| a & b)
+    }
+}
+
+impl BitOr for &BigInt {
+    type Output = BigInt;
+
+    fn bitor(self, other: Self) -> BigInt {
+        bitwise(self, other, |a, b| a | b)
+    }
+}
+
+impl BitXor for &BigInt {
+    type Output = BigInt;
+
+    fn bitxor(self, other: Self) -> BigInt {
+        bitwise(self, other, |a, b| a ^ b)
+    }
+}
+
+impl Shl<usize> for &BigInt {
+    type Output = BigInt;
+
+    fn shl(self, bits: usize) -> BigInt {
+        BigInt::new(self.negative, shift_left(&self.limbs, bits))
+    }
+}
+
+// Rounds toward negative infinity, like an arithmetic shift.
+impl Shr<usize> for &BigInt {
+    type Output = BigInt;
+
+    fn shr(self, bits: usize) -> BigInt {
+        if !self.negative {
+            return BigInt::new(false, shift_right(&self.limbs, bits));
+        }
+        // -a >> n == -(((a - 1) >> n) + 1)
+        let one = [1];
+        let shifted = shift_right(&sub(&self.limbs, &one), bits);
+        BigInt::new(true, add(&shifted, &one))
+    }
+}
+
+impl Neg for &BigInt {
+    type Output = BigInt;
+
+    fn neg(self) -> BigInt {
+        BigInt::new(!self.negative, self.limbs.clone())
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        // Peel off nine decimal digits at a time, least significant first.
+        let mut chunks = Vec::new();
+        let mut limbs = self.limbs.clone();
+        while !limbs.is_empty() {
+            chunks.push(div_small(&mut limbs, 1_000_000_000));
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+fn from_u64(n: u64) -> Vec<u32> {
+    let mut limbs = vec![n as u32, (n >> 32) as u32];
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    limbs
+}
+
+fn compare(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = Vec::with_capacity(long.len() + 1);
+    let mut carry = 0u64;
+    for (i, &limb) in long.iter().enumerate() {
+        let sum = limb as u64 + *short.get(i).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    if carry > 0 {
+        out.push(carry as u32);
+    }
+    out
+}
+
+// a - b, where a >= b.
+fn sub(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut difference = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if difference < 0 {
+            difference += 1 << 32;
+            borrow = 1;
+        }
+        out.push(difference as u32);
+    }
+    out
+}
+
+fn mul(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let product = x as u64 * y as u64 + out[i + j] as u64 + carry;
+            out[i + j] = product as u32;
+            carry = product >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    out
+}
+
+// limbs = limbs * factor + addend
+fn mul_small_add(limbs: &mut Vec<u32>, factor: u32, addend: u32) {
+    let mut carry = addend as u64;
+    for limb in limbs.iter_mut() {
+        let product = *limb as u64 * factor as u64 + carry;
+        *limb = product as u32;
+        carry = product >> 32;
+    }
+    if carry > 0 {
+        limbs.push(carry as u32);
+    }
+}
+
+// Divides in place and returns the remainder.
+fn div_small(limbs: &mut Vec<u32>, divisor: u32) -> u32 {
+    let mut remainder = 0u64;
+    for limb in limbs.iter_mut().rev() {
+        let current = remainder << 32 | *limb as u64;
+        *limb = (current / divisor as u64) as u32;
+        remainder = current % divisor as u64;
+    }
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+    remainder as u32
+}
+
+fn shift_left(limbs: &[u32], bits: usize) -> Vec<u32> {
+    let mut out = vec![0u32; bits / 32];
+    let shift = bits % 32;
+    let mut carry = 0u32;
+    for &limb in limbs {
+        if shift == 0 {
+            out.push(limb);
+        } else {
+            out.push(limb << shift | carry);
+            carry = limb >> (32 - shift);
+        }
+    }
+    if carry > 0 {
+        out.push(carry);
+    }
+    out
+}
+
+fn shift_right(limbs: &[u32], bits: usize) -> Vec<u32> {
+    let skip = bits / 32;
+    let shift = bits % 32;
+    let mut out = Vec::with_capacity(limbs.len().saturating_sub(skip));
+    for i in skip..limbs.len() {
+        let high = limbs.get(i + 1).copied().unwrap_or(0);
+        if shift == 0 {
+            out.push(limbs[i]);
+        } else {
+            out.push(limbs[i] >> shift | high << (32 - shift));
+        }
+    }
+    out
+}
+
+// Two's complement limbs, sign-extended to `len`.
+fn twos_complement(n: &BigInt, len: usize) -> Vec<u32> {
+    let mut limbs = n.limbs.clone();
+    limbs.resize(len, 0);
+    if n.negative {
+        let mut carry = true;
+        for limb in limbs.iter_mut() {
+            let (sum, overflow) = (!*limb).overflowing_add(carry as u32);
+            *limb = sum;
+            carry = overflow;
+        }
+    }
+    limbs
+}
+
+fn bitwise(a: &BigInt, b: &BigInt, op: fn(u32, u32) -> u32) -> BigInt {
+    // One spare limb holds the sign bit.
+    let len = a.limbs.len().max(b.limbs.len()) + 1;
+    let x = twos_complement(a, len);
+    let y = twos_complement(b, len);
+    let limbs: Vec<u32> = x.iter().zip(&y).map(|(&x, &y)| op(x, y)).collect();
+
+    if limbs[len - 1] >> 31 == 1 {
+        // Negative: the magnitude is the inverted bits plus one.
+        let inverted: Vec<u32> = limbs.iter().map(|&limb| !limb).collect();
+        return BigInt::new(true, add(&inverted, &[1]));
+    }
+    BigInt::new(false, limbs)
+}
+
+// Schoolbook binary long division: one shift and compare per bit of the
+// dividend. Plenty for the sizes scripts work with.
+fn divide(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if let [divisor] = b {
+        let mut quotient = a.to_vec();
+        let remainder = div_small(&mut quotient, *divisor);
+        return (quotient, from_u64(remainder as u64));
+    }
+
+    let mut quotient = vec![0u32; a.len()];
+    let mut remainder: Vec<u32> = Vec::new();
+    for i in (0..a.len() * 32).rev() {
+        remainder = shift_left(&remainder, 1);
+        if a[i / 32] >> (i % 32) & 1 == 1 {
+            match remainder.first_mut() {
+                Some(low) => *low |= 1,
+                None => remainder.push(1),
+            }
+        }
+        if compare(&remainder, b) != Ordering::Less {
+            remainder = sub(&remainder, b);
+            while remainder.last() == Some(&0) {
+                remainder.pop();
+            }
+            quotient[i / 32] |= 1 << (i % 32);
+        }
+    }
+    (quotient, remainder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value::Value;
+
+    fn big(text: &str) -> BigInt {
+        match text.strip_prefix('-') {
+            Some(digits) => -&BigInt::parse(digits, 10).unwrap(),
+            None => BigInt::parse(text, 10).unwrap(),
+        }
+    }
+
+    // The variant an integer Value landed in, with its digits.
+    fn integer(value: Result<Value, crate::error::RuntimeError>) -> (&'static str, String) {
+        match value {
+            Ok(Value::Int(n)) => ("int", n.to_string()),
+            Ok(Value::BigInt(n)) => ("bigint", n.to_string()),
+            other => panic!(
+                "expected an integer, got {:?}",
+                other.map_err(|e| e.message)
+            ),
+        }
+    }
+
+    fn int(digits: &str) -> (&'static str, String) {
+        ("int", digits.to_string())
+    }
+
+    fn bigint(digits: &str) -> (&'static str, String) {
+        ("bigint", digits.to_string())
+    }
+
+    const MAX: &str = "9223372036854775807";
+    const MIN: &str = "-9223372036854775808";
+    const MAX_PLUS_ONE: &str = "9223372036854775808";
+    const MIN_MINUS_ONE: &str = "-9223372036854775809";
+
+    #[test]
+    fn parses_and_displays() {
+        for text in [
+            "0",
+            "1",
+            "-1",
+            "4294967295",
+            "4294967296",
+            MIN,
+            MAX_PLUS_ONE,
+            "-1000000000000000000000000000001",
+        ] {
+            assert_eq!(big(text).to_string(), text);
+        }
+        assert_eq!(big("-0"), BigInt::from(0));
+        assert!(!big("-0").is_negative());
+        assert_eq!(BigInt::parse("ff", 16), Some(BigInt::from(255)));
+        assert_eq!(BigInt::parse("102", 2), None);
+        assert_eq!(BigInt::parse("", 10), None);
+    }
+
+    #[test]
+    fn converts_at_the_i64_boundaries() {
+        assert_eq!(big(MAX).to_i64(), Some(i64::MAX));
+        assert_eq!(big(MIN).to_i64(), Some(i64::MIN));
+        assert_eq!(big(MAX_PLUS_ONE).to_i64(), None);
+        assert_eq!(big(MIN_MINUS_ONE).to_i64(), None);
+        assert_eq!(BigInt::from(i64::MIN), big(MIN));
+        assert_eq!(-&BigInt::from(i64::MIN), big(MAX_PLUS_ONE));
+        assert_eq!(BigInt::from_f64(-9223372036854775808.0), Some(big(MIN)));
+        assert_eq!(
+            BigInt::from_f64(9223372036854775808.0),
+            Some(big(MAX_PLUS_ONE))
+        );
+        assert_eq!(BigInt::from_f64(0.5), None);
+        assert_eq!(big(MAX_PLUS_ONE).to_f64(), 9223372036854775808.0);
+    }
+
+    #[test]
+    fn promotes_on_overflow() {
+        let max = || Value::Int(i64::MAX);
+        let min = || Value::Int(i64::MIN);
+        assert_eq!(integer(max() + Value::Int(1)), bigint(MAX_PLUS_ONE));
+        assert_eq!(integer(min() - Value::Int(1)), bigint(MIN_MINUS_ONE));
+        assert_eq!(integer(-min()), bigint(MAX_PLUS_ONE));
+        assert_eq!(integer(min() * Value::Int(-1)), bigint(MAX_PLUS_ONE));
+        assert_eq!(integer(min() / Value::Int(-1)), bigint(MAX_PLUS_ONE));
+        assert_eq!(
+            integer(min().floor_div(Value::Int(-1))),
+            bigint(MAX_PLUS_ONE)
+        );
+        assert_eq!(
+            integer(max() * max()),
+            bigint("85070591730234615847396907784232501249")
+        );
+        assert_eq!(
+            integer(Value::Int(2).pow(Value::Int(64))),
+            bigint("18446744073709551616")
+        );
+        assert_eq!(
+            integer(Value::Int(1) << Value::Int(63)),
+            bigint(MAX_PLUS_ONE)
+        );
+        // Results that fit stay Ints.
+        assert_eq!(integer(min() + Value::Int(0)), int(MIN));
+        assert_eq!(integer(min() % Value::Int(-1)), int("0"));
+        assert_eq!(integer(Value::Int(-1) << Value::Int(63)), int(MIN));
+    }
+
+    #[test]
+    fn demotes_results_that_fit() {
+        let big_value = |text: &str| Value::BigInt(big(text));
+        assert_eq!(integer(big_value(MAX_PLUS_ONE) - Value::Int(1)), int(MAX));
+        assert_eq!(integer(big_value(MIN_MINUS_ONE) + Value::Int(1)), int(MIN));
+        assert_eq!(integer(-big_value(MAX_PLUS_ONE)), int(MIN));
+        assert_eq!(
+            integer(big_value(MAX_PLUS_ONE) / Value::Int(2)),
+            int("4611686018427387904")
+        );
+        assert_eq!(integer(big_value(MAX_PLUS_ONE) % Value::Int(10)), int("8"));
+        assert_eq!(
+            integer(big_value(MAX_PLUS_ONE) >> Value::Int(1)),
+            int("4611686018427387904")
+        );
+        assert_eq!(
+            integer(big_value(MAX_PLUS_ONE) - big_value(MAX_PLUS_ONE)),
+            int("0")
+        );
+        assert_eq!(
+            integer(big_value(MIN_MINUS_ONE) & Value::Int(0xFF)),
+            int("255")
+        );
+    }
+
+    #[test]
+    fn division_truncates_and_remainder_follows_the_dividend() {
+        // Scaled past i64 so the limb arithmetic does the work.
+        let scale = &BigInt::from(1) << 70;
+        for (a, b, q, r) in [
+            (7, 3, 2, 1),
+            (-7, 3, -2, -1),
+            (7, -3, -2, 1),
+            (-7, -3, 2, -1),
+            (6, -3, -2, 0),
+        ] {
+            let (a, b) = (BigInt::from(a), BigInt::from(b));
+            assert_eq!(a.div_rem(&b), (BigInt::from(q), BigInt::from(r)));
+            let (quotient, remainder) = (&a * &scale).div_rem(&(&b * &scale));
+            assert_eq!(quotient, BigInt::from(q));
+            assert_eq!(remainder, &BigInt::from(r) * &scale);
+        }
+        assert!(!BigInt::from(-6).div_rem(&BigInt::from(3)).1.is_negative());
+    }
+
+    #[test]
+    fn floor_division_and_remainder_follow_the_divisor() {
+        let scale = &BigInt::from(1) << 70;
+        for (a, b, q, r) in [
+            (7, 3, 2, 1),
+            (-7, 3, -3, 2),
+            (7, -3, -3, -2),
+            (-7, -3, 2, -1),
+            (-6, 3, -2, 0),
+        ] {
+            let (a, b) = (BigInt::from(a), BigInt::from(b));
+            assert_eq!(a.div_rem_floor(&b), (BigInt::from(q), BigInt::from(r)));
+            let (quotient, remainder) = (&a * &scale).div_rem_floor(&(&b * &scale));
+            assert_eq!(quotient, BigInt::from(q));
+            assert_eq!(remainder, &BigInt::from(r) * &scale);
+
+            let value = |n: &BigInt| Value::BigInt(n * &scale);
+            assert_eq!(integer(value(&a).floor_div(value(&b))), int(&q.to_string()));
+            assert_eq!(
+                integer(value(&a) % value(&b)),
+                integer(Ok(Value::integer(&BigInt::from(r) * &scale)))
+            );
+            assert_eq!(
+                integer(value(&a) / value(&b)),
+                integer(Ok(Value::integer(a.div_rem(&b).0)))
+            );
+        }
+        let big_value = Value::BigInt(big(MAX_PLUS_ONE));
+        assert_eq!(
+            (big_value.clone() / Value::Int(0)).unwrap_err().message,
+            "Division by zero."
+        );
+        assert_eq!(
+            (big_value % Value::Int(0)).unwrap_err().message,
+            "Division by zero."
+        );
+    }
+
+    #[test]
+    fn divides_multi_limb_numbers() {
+        let a = big("123456789012345678901234567890123456789");
+        let b = big("-98765432109876543210");
+        let (quotient, remainder) = a.div_rem(&b);
+        assert_eq!(quotient, big("-1249999988609375000"));
+        assert_eq!(&(&quotient * &b) + &remainder, a);
+        assert_eq!(remainder, big("15297067891529706789"));
+    }
+
+    #[test]
+    fn shifts_and_masks_as_twos_complement() {
+        assert_eq!(&BigInt::from(-5) >> 1, BigInt::from(-3));
+        assert_eq!(&BigInt::from(-1) >> 100, BigInt::from(-1));
+        assert_eq!(&big(MIN_MINUS_ONE) >> 64, BigInt::from(-1));
+        assert_eq!(&BigInt::from(-1) << 64, big("-18446744073709551616"));
+        assert_eq!(&big(MIN_MINUS_ONE) & &BigInt::from(-1), big(MIN_MINUS_ONE));
+        assert_eq!(&big(MIN_MINUS_ONE) | &BigInt::from(0), big(MIN_MINUS_ONE));
+        assert_eq!(&big(MIN) ^ &BigInt::from(-1), big(MAX));
+        assert_eq!(big(MAX_PLUS_ONE).bits(), 64);
+        assert_eq!(BigInt::from(-3).pow(3), BigInt::from(-27));
+    }
+
+    #[test]
+    fn rejects_oversized_results() {
+        let error = |value: Result<Value, crate::error::RuntimeError>| value.unwrap_err().message;
+        let limit = crate::value::MAX_RESULT_BITS as i64;
+        assert_eq!(
+            error(Value::Int(3).pow(Value::Int(1_000_000))),
+            "Integer result of '**' is too large."
+        );
+        assert_eq!(
+            error(Value::Int(2).pow(Value::Int(limit + 1))),
+            "Integer result of '**' is too large."
+        );
+        assert_eq!(
+            error(Value::Int(1) << Value::Int(limit)),
+            "Integer result of '<<' is too large."
+        );
+        assert_eq!(
+            error(Value::BigInt(big(MAX_PLUS_ONE)) << Value::Int(limit)),
+            "Integer result of '<<' is too large."
+        );
+        // Results within the limit still work, as do huge powers of 0, 1 and -1.
+        assert_eq!(
+            integer(Value::Int(2).pow(Value::Int(limit / 2))).0,
+            "bigint"
+        );
+        assert_eq!(integer(Value::Int(1) << Value::Int(limit - 1)).0, "bigint");
+        assert_eq!(integer(Value::Int(-1).pow(Value::Int(i64::MAX))), int("-1"));
+        assert_eq!(integer(Value::Int(0) << Value::Int(limit * 2)), int("0"));
+    }
+}
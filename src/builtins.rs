@@ -0,0 +1,290 @@
+use crate::error::*;
+use crate::iter::{Iter, Range};
+use crate::value::*;
+use std::rc::Rc;
+
+// Indexing and the methods of the built-in types. Errors are returned for
+// the VM to raise as runtime errors.
+
+pub fn index_get(object: &Value, index: &Value) -> Result<Value, RuntimeError> {
+    match object {
+        Value::List(list) => {
+            let list = list.borrow();
+            let at = position(index, list.len(), "List")?;
+            Ok(list[at].clone())
+        }
+        Value::Map(map) => match map.borrow().get(index)? {
+            Some(value) => Ok(value.clone()),
+            None => Err(key_error(format!(
+                "Key {} not found in map.",
+                index.literal()
+            ))),
+        },
+        Value::Str(s) => {
+            let count = s.chars().count();
+            let at = position(index, count, "String")?;
+            Ok(Value::Str(s.chars().nth(at).unwrap().to_string()))
+        }
+        _ => Err(type_error("Only lists, maps and strings can be indexed.")),
+    }
+}
+
+pub fn index_set(object: &Value, index: &Value, value: Value) -> Result<(), RuntimeError> {
+    match object {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let at = position(index, list.len(), "List")?;
+            list[at] = value;
+            Ok(())
+        }
+        Value::Map(map) => map.borrow_mut().insert(index.clone(), value),
+        Value::Str(_) => Err(type_error("Strings cannot be modified.")),
+        _ => Err(type_error(
+            "Only lists and maps can be assigned to by index.",
+        )),
+    }
+}
+
+// `start..end` or `start..=end`, counting up by one.
+pub fn range(start: &Value, end: &Value, inclusive: bool) -> Result<Value, RuntimeError> {
+    Ok(Value::Range(Range {
+        start: range_bound(start)?,
+        end: range_bound(end)?,
+        step: 1,
+        inclusive,
+    }))
+}
+
+fn range_bound(value: &Value) -> Result<i64, RuntimeError> {
+    match value {
+        Value::Int(n) => Ok(*n),
+        Value::BigInt(_) => Err(value_error("Range bounds must fit in 64 bits.")),
+        _ => Err(type_error("Range bounds must be integers.")),
+    }
+}
+
+// The iterator a `for` loop steps through.
+pub fn iterate(value: &Value) -> Result<Iter, RuntimeError> {
+    match value {
+        Value::List(list) => Ok(Iter::List(list.clone(), 0)),
+        Value::Map(map) => Ok(Iter::Map(map.clone(), 0)),
+        Value::Str(s) => Ok(Iter::Str(s.chars().collect(), 0)),
+        Value::Range(range) => Ok(Iter::Range(*range, Some(range.start))),
+        _ => Err(type_error(
+            "Only lists, maps, strings and ranges can be iterated.",
+        )),
+    }
+}
+
+// Calls `receiver.name(args)`.
+pub fn invoke(receiver: &Value, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match receiver {
+        Value::List(list) => list_method(list, name, args),
+        Value::Map(map) => map_method(map, name, args),
+        Value::Range(range) => range_method(range, name, args),
+        Value::Str(s) => string_method(s, name, args),
+        Value::Error(error) => error_method(error, name, args),
+        _ => Err(type_error(format!("Undefined method '{}'.", name))),
+    }
+}
+
+fn list_method(list: &Rc<ListCell>, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match name {
+        "len" => {
+            arity(name, args, 0, 0)?;
+            Ok(Value::Int(list.borrow().len() as i64))
+        }
+        "push" => {
+            arity(name, args, 1, 1)?;
+            list.borrow_mut().push(args[0].clone());
+            Ok(Value::Nil)
+        }
+        "pop" => {
+            arity(name, args, 0, 0)?;
+            list.borrow_mut()
+                .pop()
+                .ok_or_else(|| index_error("Cannot pop from an empty list."))
+        }
+        "insert" => {
+            arity(name, args, 2, 2)?;
+            let mut list = list.borrow_mut();
+            // Inserting at the length appends.
+            let at = position(&args[0], list.len() + 1, "List")?;
+            list.insert(at, args[1].clone());
+            Ok(Value::Nil)
+        }
+        "remove" => {
+            arity(name, args, 1, 1)?;
+            let mut list = list.borrow_mut();
+            let at = position(&args[0], list.len(), "List")?;
+            Ok(list.remove(at))
+        }
+        "slice" => {
+            arity(name, args, 1, 2)?;
+            let list = list.borrow();
+            let start = clamped(&args[0], list.len())?;
+            let end = match args.get(1) {
+                Some(end) => clamped(end, list.len())?,
+                None => list.len(),
+            };
+            let items = list.get(start..end.max(start)).unwrap_or_default().to_vec();
+            Ok(Value::list(items))
+        }
+        "sort" => {
+            arity(name, args, 0, 0)?;
+            let mut list = list.borrow_mut();
+            let comparable = list
+                .windows(2)
+                .all(|pair| pair[0].partial_cmp(&pair[1]).is_some());
+            if !comparable {
+                return Err(type_error(
+                    "List elements must be all numbers or all strings to sort.",
+                ));
+            }
+            list.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            Ok(Value::Nil)
+        }
+        _ => Err(type_error(format!("Undefined method '{}' for list.", name))),
+    }
+}
+
+fn map_method(map: &Rc<MapCell>, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match name {
+        "len" => {
+            arity(name, args, 0, 0)?;
+            Ok(Value::Int(map.borrow().entries().len() as i64))
+        }
+        "keys" => {
+            arity(name, args, 0, 0)?;
+            let keys = map.borrow().entries().iter().map(|(key, _)| key.clone()).collect();
+            Ok(Value::list(keys))
+        }
+        "values" => {
+            arity(name, args, 0, 0)?;
+            let values = map.borrow().entries().iter().map(|(_, value)| value.clone()).collect();
+            Ok(Value::list(values))
+        }
+        "contains" => {
+            arity(name, args, 1, 1)?;
+            Ok(Value::Boolean(map.borrow().get(&args[0])?.is_some()))
+        }
+        "remove" => {
+            arity(name, args, 1, 1)?;
+            map.borrow_mut()
+                .remove(&args[0])?
+                .ok_or_else(|| key_error(format!("Key {} not found in map.", args[0].literal())))
+        }
+        _ => Err(type_error(format!("Undefined method '{}' for map.", name))),
+    }
+}
+
+fn range_method(range: &Range, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match name {
+        "len" => {
+            arity(name, args, 0, 0)?;
+            i64::try_from(range.count())
+                .map(Value::Int)
+                .map_err(|_| arithmetic_error("Range is too long to measure."))
+        }
+        "contains" => {
+            arity(name, args, 1, 1)?;
+            Ok(Value::Boolean(match args[0] {
+                Value::Int(n) => range.contains(n),
+                _ => false,
+            }))
+        }
+        // `(0..10).step(2)` visits 0, 2, 4, 6, 8. A negative step counts
+        // down: `(10..0).step(-1)`.
+        "step" => {
+            arity(name, args, 1, 1)?;
+            let step = match args[0] {
+                Value::Int(0) => return Err(value_error("Range step must not be zero.")),
+                Value::Int(step) => step,
+                _ => return Err(type_error("Range step must be an integer.")),
+            };
+            Ok(Value::Range(Range { step, ..*range }))
+        }
+        _ => Err(type_error(format!(
+            "Undefined method '{}' for range.",
+            name
+        ))),
+    }
+}
+
+fn string_method(s: &str, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match name {
+        "len" => {
+            arity(name, args, 0, 0)?;
+            Ok(Value::Int(s.chars().count() as i64))
+        }
+        _ => Err(type_error(format!(
+            "Undefined method '{}' for string.",
+            name
+        ))),
+    }
+}
+
+fn error_method(error: &ErrorObject, name: &str, args: &[Value]) -> Result<Value, RuntimeError> {
+    match name {
+        "message" => {
+            arity(name, args, 0, 0)?;
+            Ok(Value::Str(error.message.clone()))
+        }
+        "kind" => {
+            arity(name, args, 0, 0)?;
+            Ok(Value::Str(error.kind.name().to_string()))
+        }
+        // One line per frame, innermost first.
+        "trace" => {
+            arity(name, args, 0, 0)?;
+            Ok(Value::list(
+                error.trace.iter().cloned().map(Value::Str).collect(),
+            ))
+        }
+        _ => Err(type_error(format!(
+            "Undefined method '{}' for error.",
+            name
+        ))),
+    }
+}
+
+fn arity(name: &str, args: &[Value], min: usize, max: usize) -> Result<(), RuntimeError> {
+    if (min..=max).contains(&args.len()) {
+        return Ok(());
+    }
+    let expected = match (min, max) {
+        (1, 1) => "1 argument".to_string(),
+        (min, max) if min == max => format!("{} arguments", min),
+        (min, max) => format!("{} to {} arguments", min, max),
+    };
+    Err(type_error(format!(
+        "{}() expects {} but got {}.",
+        name,
+        expected,
+        args.len()
+    )))
+}
+
+// Resolves an index into 0..len, counting back from the end when negative.
+fn position(index: &Value, len: usize, kind: &str) -> Result<usize, RuntimeError> {
+    let Value::Int(i) = *index else {
+        return Err(type_error(format!("{} index must be an integer.", kind)));
+    };
+    let at = if i < 0 { i + len as i64 } else { i };
+    if at < 0 || at >= len as i64 {
+        return Err(index_error(format!(
+            "{} index {} out of range for length {}.",
+            kind, i, len
+        )));
+    }
+    Ok(at as usize)
+}
+
+// Like `position`, but out-of-range indexes are clamped to 0..=len.
+fn clamped(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    let Value::Int(i) = *index else {
+        return Err(type_error("List index must be an integer."));
+    };
+    let at = if i < 0 { i + len as i64 } else { i };
+    Ok(at.clamp(0, len as i64) as usize)
+}
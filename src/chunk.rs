@@ -1,31 +1,210 @@
 use crate::value::*;
 
-pub enum OpCode {
-    OpConstant,
-    OpReturn,
-    OpNegate,
-    OpAdd,
-    OpSubtract,
-    OpMultiply,
-    OpDivide,
-    OpNil,
-    OpTrue,
-    OpFalse,
-    OpNot,
-    OpEqual,
-    OpGreater,
-    OpLess,
-    OpPrint,
-    OpPop,
-    OpDefineGlobal,
-    OpGetGlobal,
-    OpSetGlobal
+// Every instruction is described exactly once, in the `opcodes!` table at
+// the bottom of this file. The enum, byte decoding, operand layout, stack
+// effects and control flow are all generated from it, so adding an
+// instruction means adding one row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    // One-byte index of any constant.
+    Constant,
+    // One-byte index of a string constant naming a variable.
+    Name,
+    // One-byte count, usually of the stack values the instruction
+    // consumes, such as list elements or method arguments.
+    Count,
+    // One-byte stack slot of a local variable.
+    Slot,
+    // Two-byte distance forward from the end of the instruction.
+    Jump,
+    // Two-byte distance back from the end of the instruction.
+    Loop,
+}
+
+impl Operand {
+    pub fn width(self) -> usize {
+        match self {
+            Operand::Constant | Operand::Name | Operand::Count | Operand::Slot => 1,
+            Operand::Jump | Operand::Loop => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Next,
+    Return,
+    // Always continues at the jump target.
+    Jump,
+    // Continues at either the next instruction or the jump target.
+    Branch,
+    // Never continues; control passes to a handler, if any.
+    Throw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pops {
+    Fixed(usize),
+    // The instruction's Count operand, plus a fixed number of extra values.
+    Count(usize),
+    // Two values for each of Count, such as a map entry's key and value.
+    Pairs,
+}
+
+pub struct OpInfo {
+    pub name: &'static str,
+    pub operands: &'static [Operand],
+    pub pops: Pops,
+    pub pushes: usize,
+    pub flow: Flow,
+}
+
+// Stack effects are written `2 -> 1`, or `n -> 1` / `(n + 1) -> 1` /
+// `(n * 2) -> 1` when the number of values popped comes from the
+// instruction's Count operand.
+macro_rules! pops {
+    (n) => {
+        Pops::Count(0)
+    };
+    ((n * 2)) => {
+        Pops::Pairs
+    };
+    ((n + $extra:literal)) => {
+        Pops::Count($extra)
+    };
+    ($fixed:literal) => {
+        Pops::Fixed($fixed)
+    };
+}
+
+macro_rules! opcodes {
+    ($($name:ident($($operand:ident),*) $pops:tt -> $pushes:literal $flow:ident,)*) => {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        #[allow(clippy::enum_variant_names)]
+        pub enum OpCode {
+            $($name,)*
+        }
+
+        const OPCODES: &[OpCode] = &[$(OpCode::$name,)*];
+
+        const OPCODE_INFO: &[OpInfo] = &[$(OpInfo {
+            name: stringify!($name),
+            operands: &[$(Operand::$operand),*],
+            pops: pops!($pops),
+            pushes: $pushes,
+            flow: Flow::$flow,
+        },)*];
+    };
+}
+
+impl OpCode {
+    pub fn all() -> &'static [OpCode] {
+        OPCODES
+    }
+
+    pub fn info(self) -> &'static OpInfo {
+        &OPCODE_INFO[self as usize]
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().name
+    }
+
+    // Size of the instruction in bytes, opcode included.
+    pub fn width(self) -> usize {
+        1 + self.info().operands.iter().map(|o| o.width()).sum::<usize>()
+    }
+}
+
+pub struct Instruction {
+    pub offset: usize,
+    pub opcode: OpCode,
+    pub operands: Vec<usize>,
+    pub line: usize,
+    pub resolved_constant: Option<Value>,
+}
+
+impl Instruction {
+    pub fn next(&self) -> usize {
+        self.offset + self.opcode.width()
+    }
+
+    // (values popped, values pushed)
+    pub fn stack_effect(&self) -> (usize, usize) {
+        let info = self.opcode.info();
+        let count = || {
+            let index = info.operands.iter().position(|&o| o == Operand::Count);
+            self.operands[index.unwrap()]
+        };
+        let pops = match info.pops {
+            Pops::Fixed(n) => n,
+            Pops::Count(extra) => count() + extra,
+            Pops::Pairs => count() * 2,
+        };
+        (pops, info.pushes)
+    }
+
+    // Where a Jump or Loop operand leads. None if the instruction has no
+    // such operand, or if it leads back past the start of the code.
+    pub fn jump_target(&self) -> Option<usize> {
+        let info = self.opcode.info();
+        let index = info
+            .operands
+            .iter()
+            .position(|&o| o == Operand::Jump || o == Operand::Loop)?;
+        let distance = self.operands[index];
+        match info.operands[index] {
+            Operand::Jump => Some(self.next() + distance),
+            _ => self.next().checked_sub(distance),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    UnknownOpcode { offset: usize, byte: u8 },
+    TruncatedOperand { offset: usize, opcode: OpCode },
+}
+
+// Decodes a chunk front to back, stopping after the first error.
+pub struct Instructions<'a> {
+    chunk: &'a Chunk,
+    offset: usize,
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<Instruction, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.chunk.code.len() {
+            return None;
+        }
+        let decoded = self.chunk.decode(self.offset);
+        self.offset = match &decoded {
+            Ok(instruction) => instruction.next(),
+            Err(_) => self.chunk.code.len(),
+        };
+        Some(decoded)
+    }
+}
+
+// Catches errors raised by instructions starting in `start..end`: the stack
+// is cut back to `depth` values, the error is pushed, and execution
+// continues at `target`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Handler {
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub depth: usize,
 }
 
 pub struct Chunk {
     pub code: Vec<u8>,
     pub lines: Vec<usize>,
     pub constants: ValueArray,
+    // Searched in order, so a handler nested inside another comes first.
+    pub handlers: Vec<Handler>,
 }
 
 impl Chunk {
@@ -34,9 +213,17 @@ impl Chunk {
             code: Vec::new(),
             lines: Vec::new(),
             constants: ValueArray::new(),
+            handlers: Vec::new(),
         }
     }
 
+    // The handler for an error raised by the instruction at `offset`.
+    pub fn handler(&self, offset: usize) -> Option<&Handler> {
+        self.handlers
+            .iter()
+            .find(|handler| (handler.start..handler.end).contains(&offset))
+    }
+
     pub fn write(&mut self, byte: u8, line: usize) {
         self.code.push(byte);
         self.lines.push(line);
@@ -46,14 +233,10 @@ impl Chunk {
         self.code[offset]
     }
 
-    pub fn write_opcode(&mut self, opcode: OpCode, line: usize) {
-        self.code.push(opcode.into());
-        self.lines.push(line);
-    }
-
     pub fn free(&mut self) {
         self.code = Vec::new();
         self.constants.free();
+        self.handlers = Vec::new();
     }
 
     pub fn add_constant(&mut self, value: Value) -> Option<u8> {
@@ -62,62 +245,123 @@ impl Chunk {
     }
 
     pub fn disassemble(&self, name: &str) {
-        println!("== {} ==", name);
+        print!("{}", self.listing(name));
+    }
+
+    // What `disassemble` prints, which the assembler reads back.
+    pub fn listing(&self, name: &str) -> String {
+        let mut out = format!("== {} ==\n", name);
 
         let mut offset = 0;
         while offset < self.code.len() {
-            offset = self.disassemble_instruction(offset);
+            offset = self.list_instruction(offset, &mut out);
         }
+
+        // In the assembler's syntax: start, end, target, depth.
+        for handler in &self.handlers {
+            out.push_str(&format!(
+                ".handler {:04} {:04} {:04} {}\n",
+                handler.start, handler.end, handler.target, handler.depth
+            ));
+        }
+        out
     }
 
+    #[cfg(feature = "debug_trace_execution")]
     pub fn disassemble_instruction(&self, offset: usize) -> usize {
-        print!("{:04} ", offset);
+        let mut out = String::new();
+        let next = self.list_instruction(offset, &mut out);
+        print!("{}", out);
+        next
+    }
+
+    // Appends the instruction at `offset` to `out`, returning the offset of
+    // the next one.
+    fn list_instruction(&self, offset: usize, out: &mut String) -> usize {
+        out.push_str(&format!("{:04} ", offset));
 
         if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
-            print!("   | ");
+            out.push_str("   | ");
         } else {
-            print!("{:4} ", self.lines[offset]);
+            out.push_str(&format!("{:4} ", self.lines[offset]));
         }
 
-        let instruction = self.code[offset];
-        match instruction {
-            0 => self.constant_instruction("OpConstant", offset),
-            1 => self.simple_instruction("OpReturn", offset),
-            2 => self.simple_instruction("OpNegate", offset),
-            3 => self.simple_instruction("OpAdd", offset),
-            4 => self.simple_instruction("OpSubtract", offset),
-            5 => self.simple_instruction("OpMultiply", offset),
-            6 => self.simple_instruction("OpDivide", offset),
-            7 => self.simple_instruction("OpNil", offset),
-            8 => self.simple_instruction("OpTrue", offset),
-            9 => self.simple_instruction("OpFalse", offset),
-            10 => self.simple_instruction("OpNot", offset),
-            11 => self.simple_instruction("OpEqual", offset),
-            12 => self.simple_instruction("OpGreater", offset),
-            13 => self.simple_instruction("OpLess", offset),
-            14 => self.simple_instruction("OpPrint", offset),
-            15 => self.simple_instruction("OpPop", offset),
-            16 => self.constant_instruction("OpDefineGlobal", offset),
-            17 => self.simple_instruction("OpGetGlobal", offset),
-            18 => self.constant_instruction("OpSetGlobal", offset),
-            _ => {
-                println!("Unknown opcode {}", instruction);
+        match self.decode(offset) {
+            Ok(instruction) => {
+                let name = instruction.opcode.name();
+                let Some((first, rest)) = instruction.operands.split_first() else {
+                    out.push_str(&format!("{}\n", name));
+                    return instruction.next();
+                };
+                // The constant's literal comes last, after every operand.
+                // Jumps are shown by their target offset.
+                let shown = |index: usize, operand: usize| {
+                    match instruction.opcode.info().operands[index] {
+                        Operand::Jump | Operand::Loop => instruction.jump_target().unwrap_or(0),
+                        _ => operand,
+                    }
+                };
+                out.push_str(&format!("{:<16} {:4}", name, shown(0, *first)));
+                for (index, &operand) in rest.iter().enumerate() {
+                    out.push_str(&format!(" {}", shown(index + 1, operand)));
+                }
+                match &instruction.resolved_constant {
+                    Some(constant) => out.push_str(&format!(" '{}'\n", constant.literal())),
+                    None => out.push('\n'),
+                }
+                instruction.next()
+            }
+            Err(DecodeError::UnknownOpcode { byte, .. }) => {
+                out.push_str(&format!("Unknown opcode {}\n", byte));
                 offset + 1
             }
+            Err(DecodeError::TruncatedOperand { opcode, .. }) => {
+                out.push_str(&format!("{} <missing operand>\n", opcode.name()));
+                self.code.len()
+            }
         }
     }
 
-    fn simple_instruction(&self, name: &str, offset: usize) -> usize {
-        println!("{}", name);
-        offset + 1
+    pub fn decode(&self, offset: usize) -> Result<Instruction, DecodeError> {
+        let byte = self.code[offset];
+        let opcode =
+            OpCode::try_from(byte).map_err(|byte| DecodeError::UnknownOpcode { offset, byte })?;
+
+        if offset + opcode.width() > self.code.len() {
+            return Err(DecodeError::TruncatedOperand { offset, opcode });
+        }
+
+        let mut operands = Vec::new();
+        let mut resolved_constant = None;
+        let mut at = offset + 1;
+        for operand in opcode.info().operands {
+            let value = self.code[at..at + operand.width()]
+                .iter()
+                .fold(0, |value, &byte| value << 8 | byte as usize);
+            match operand {
+                Operand::Constant | Operand::Name => {
+                    resolved_constant = self.constants.values.get(value).cloned()
+                }
+                Operand::Count | Operand::Slot | Operand::Jump | Operand::Loop => {}
+            }
+            operands.push(value);
+            at += operand.width();
+        }
+
+        Ok(Instruction {
+            offset,
+            opcode,
+            operands,
+            line: self.lines.get(offset).copied().unwrap_or(0),
+            resolved_constant,
+        })
     }
 
-    fn constant_instruction(&self, name: &str, offset: usize) -> usize {
-        let constant = self.code[offset + 1];
-        print!("{:<16} {:4} '", name, constant);
-        self.constants.print_value(constant.into());
-        println!("'");
-        offset + 2
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            chunk: self,
+            offset: 0,
+        }
     }
 
     pub fn get_constant(&self, index: usize) -> Value {
@@ -125,30 +369,11 @@ impl Chunk {
     }
 }
 
-impl From<u8> for OpCode {
-    fn from(byte: u8) -> Self {
-        match byte {
-            0 => OpCode::OpConstant,
-            1 => OpCode::OpReturn,
-            2 => OpCode::OpNegate,
-            3 => OpCode::OpAdd,
-            4 => OpCode::OpSubtract,
-            5 => OpCode::OpMultiply,
-            6 => OpCode::OpDivide,
-            7 => OpCode::OpNil,
-            8 => OpCode::OpTrue,
-            9 => OpCode::OpFalse,
-            10 => OpCode::OpNot,
-            11 => OpCode::OpEqual,
-            12 => OpCode::OpGreater,
-            13 => OpCode::OpLess,
-            14 => OpCode::OpPrint,
-            15 => OpCode::OpPop,
-            16 => OpCode::OpDefineGlobal,
-            17 => OpCode::OpGetGlobal,
-            18 => OpCode::OpSetGlobal,
-            _ => panic!("Unknown opcode {}", byte),
-        }
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, u8> {
+        OPCODES.get(byte as usize).copied().ok_or(byte)
     }
 }
 
@@ -157,3 +382,64 @@ impl From<OpCode> for u8 {
         opcode as u8
     }
 }
+
+opcodes! {
+    OpConstant(Constant) 0 -> 1 Next,
+    OpReturn() 0 -> 0 Return,
+    OpNegate() 1 -> 1 Next,
+    OpAdd() 2 -> 1 Next,
+    OpSubtract() 2 -> 1 Next,
+    OpMultiply() 2 -> 1 Next,
+    OpDivide() 2 -> 1 Next,
+    OpNil() 0 -> 1 Next,
+    OpTrue() 0 -> 1 Next,
+    OpFalse() 0 -> 1 Next,
+    OpNot() 1 -> 1 Next,
+    OpEqual() 2 -> 1 Next,
+    OpGreater() 2 -> 1 Next,
+    OpLess() 2 -> 1 Next,
+    OpPrint() 1 -> 0 Next,
+    OpPop() 1 -> 0 Next,
+    OpDefineGlobal(Name) 1 -> 0 Next,
+    OpGetGlobal(Name) 0 -> 1 Next,
+    OpSetGlobal(Name) 1 -> 1 Next,
+    OpBuildString(Count) n -> 1 Next,
+    OpModulo() 2 -> 1 Next,
+    OpFloorDivide() 2 -> 1 Next,
+    OpPower() 2 -> 1 Next,
+    OpBitAnd() 2 -> 1 Next,
+    OpBitOr() 2 -> 1 Next,
+    OpBitXor() 2 -> 1 Next,
+    OpBitNot() 1 -> 1 Next,
+    OpShiftLeft() 2 -> 1 Next,
+    OpShiftRight() 2 -> 1 Next,
+    OpBuildList(Count) n -> 1 Next,
+    OpIndexGet() 2 -> 1 Next,
+    OpIndexSet() 3 -> 1 Next,
+    OpDupTwo() 2 -> 4 Next,
+    OpInvoke(Name, Count) (n + 1) -> 1 Next,
+    OpBuildMap(Count) (n * 2) -> 1 Next,
+    OpGetLocal(Slot) 0 -> 1 Next,
+    OpSetLocal(Slot) 1 -> 1 Next,
+    OpRange() 2 -> 1 Next,
+    OpRangeInclusive() 2 -> 1 Next,
+    OpGetIter() 1 -> 1 Next,
+    // Pushes the iterator's next value, or nil and jumps once it is done.
+    OpForIter(Jump) 1 -> 2 Branch,
+    OpLoop(Loop) 0 -> 0 Jump,
+    OpJump(Jump) 0 -> 0 Jump,
+    OpJumpIfFalse(Jump) 1 -> 0 Branch,
+    // Leave the value in place, whichever way they go.
+    OpJumpIfNil(Jump) 1 -> 1 Branch,
+    OpJumpIfNotNil(Jump) 1 -> 1 Branch,
+    // Pushes whether the value is a list of exactly Count elements.
+    OpMatchList(Count) 1 -> 1 Next,
+    OpDefineConstant(Name) 1 -> 0 Next,
+    // Raises the value, wrapping anything but an error.
+    OpThrow() 1 -> 0 Throw,
+    // Pops a `finally` block's pending error, or nil, and re-raises the
+    // error.
+    OpEndFinally() 1 -> 0 Next,
+    // Copies the top value below the two under it: a b c -> c a b c.
+    OpTuck() 3 -> 4 Next,
+}
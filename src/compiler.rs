@@ -1,15 +1,105 @@
+use crate::bigint::BigInt;
 use crate::chunk::*;
 use crate::scanner::*;
-use crate::token;
 use crate::token::*;
 use crate::value::*;
 use std::cell::RefCell;
+use std::collections::HashMap;
 
 pub struct Compiler<'a> {
     parser: Parser,
     scanner: Scanner,
     chunk: &'a mut Chunk,
     rules: Vec<ParseRule<'a>>,
+    // The most recent plain read of a place, with the code range it
+    // compiled to.
+    last_read: Option<(Place, usize, usize)>,
+    // Locals in scope, innermost last. A local's index is its stack slot.
+    locals: Vec<Local>,
+    scope_depth: usize,
+    // Enclosing loops, innermost last.
+    loops: Vec<Loop>,
+    // Enclosing try statements whose `finally` block, if any, has not
+    // been reached yet, innermost last.
+    tries: Vec<Try>,
+    // Global constants declared so far, with their literal code as for
+    // locals.
+    global_constants: HashMap<String, Option<Vec<u8>>>,
+}
+
+struct Local {
+    name: String,
+    // None until the initializer has run, so `var a = a;` can be caught.
+    depth: Option<usize>,
+    constant: bool,
+    // For a constant initialized with a literal, the literal's code, which
+    // reads of the constant emit instead of a load.
+    literal: Option<Vec<u8>>,
+}
+
+struct Loop {
+    label: Option<String>,
+    // Where `continue` jumps to.
+    start: usize,
+    // How many locals `continue` and `break` leave on the stack.
+    continue_locals: usize,
+    break_locals: usize,
+    // `break` jumps to patch once the end of the loop is known.
+    breaks: Vec<usize>,
+}
+
+struct Try {
+    // How many loops enclose the try statement.
+    loops: usize,
+    // How many locals are on the stack when it starts.
+    depth: usize,
+    // `break` and `continue` statements that leave it. Each jumps to the
+    // end of the statement, to be sent through the `finally` block, if
+    // any, on its way to the loop.
+    exits: Vec<Exit>,
+}
+
+struct Exit {
+    // The loop it applies to.
+    index: usize,
+    kind: ExitKind,
+    // The jump to patch once the end of the try statement is known.
+    jump: usize,
+}
+
+#[derive(Clone, Copy)]
+enum ExitKind {
+    Break,
+    Continue,
+}
+
+// A match arm's pattern. Patterns are parsed before any code is emitted
+// so that all of an arm's tests can run before any of its bindings.
+enum Pattern {
+    Wildcard,
+    Binding(String),
+    Literal(Value),
+    List(Vec<Pattern>),
+    Alternatives(Vec<Pattern>),
+}
+
+impl Pattern {
+    fn binds(&self) -> bool {
+        match self {
+            Pattern::Binding(_) => true,
+            Pattern::List(items) | Pattern::Alternatives(items) => items.iter().any(Pattern::binds),
+            Pattern::Wildcard | Pattern::Literal(_) => false,
+        }
+    }
+
+    // Whether the pattern matches every value.
+    fn is_irrefutable(&self) -> bool {
+        match self {
+            Pattern::Wildcard | Pattern::Binding(_) => true,
+            Pattern::Alternatives(items) => items.iter().any(Pattern::is_irrefutable),
+            Pattern::Literal(_) | Pattern::List(_) => false,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -19,25 +109,44 @@ struct ParseRule<'a> {
     precedence: Precedence,
 }
 
+// Anything an assignment can store into.
+#[derive(Copy, Clone)]
+enum Place {
+    Global(u8),
+    Local(u8),
+    // The list and index are on the stack.
+    Index,
+}
+
 #[derive(Default)]
 pub struct Parser {
     current: Token,
     previous: Token,
     had_error: RefCell<bool>,
     panic_mode: RefCell<bool>,
+    // Every error and warning reported, as printed.
+    diagnostics: RefCell<Vec<String>>,
 }
 
 #[derive(PartialEq, PartialOrd, Copy, Clone)]
 pub enum Precedence {
     None = 0,
     Assignment, // =
+    Conditional, // ?:
+    Coalesce,   // ??
     Or,         // or
     And,        // and
     Equality,   // == !=
     Comparison, // < > <= >=
+    Range,      // .. ..=
+    BitOr,      // |
+    BitXor,     // ^
+    BitAnd,     // &
+    Shift,      // << >>
     Term,       // + -
-    Factor,     // * /
-    Unary,      // ! -
+    Factor,     // * / % //
+    Unary,      // ! - ~
+    Exponent,   // **
     Call,       // . ()
     Primary,
 }
@@ -47,15 +156,23 @@ impl From<usize> for Precedence {
         match v {
             0 => Precedence::None,
             1 => Precedence::Assignment,
-            2 => Precedence::Or,
-            3 => Precedence::And,
-            4 => Precedence::Equality,
-            5 => Precedence::Comparison,
-            6 => Precedence::Term,
-            7 => Precedence::Factor,
-            8 => Precedence::Unary,
-            9 => Precedence::Call,
-            10 => Precedence::Primary,
+            2 => Precedence::Conditional,
+            3 => Precedence::Coalesce,
+            4 => Precedence::Or,
+            5 => Precedence::And,
+            6 => Precedence::Equality,
+            7 => Precedence::Comparison,
+            8 => Precedence::Range,
+            9 => Precedence::BitOr,
+            10 => Precedence::BitXor,
+            11 => Precedence::BitAnd,
+            12 => Precedence::Shift,
+            13 => Precedence::Term,
+            14 => Precedence::Factor,
+            15 => Precedence::Unary,
+            16 => Precedence::Exponent,
+            17 => Precedence::Call,
+            18 => Precedence::Primary,
             v => panic!("cannot convert {v} into Precedence"),
         }
     }
@@ -98,6 +215,107 @@ impl<'a> Compiler<'a> {
             precedence: Precedence::Factor,
         };
 
+        rules[TokenType::Percent as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::Factor,
+        };
+
+        rules[TokenType::SlashSlash as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::Factor,
+        };
+
+        rules[TokenType::StarStar as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::Exponent,
+        };
+
+        rules[TokenType::Ampersand as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::BitAnd,
+        };
+
+        rules[TokenType::Pipe as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::BitOr,
+        };
+
+        rules[TokenType::Caret as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::BitXor,
+        };
+
+        rules[TokenType::LessLess as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::Shift,
+        };
+
+        rules[TokenType::GreaterGreater as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::Shift,
+        };
+
+        rules[TokenType::DotDot as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::Range,
+        };
+
+        rules[TokenType::DotDotEqual as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::binary),
+            precedence: Precedence::Range,
+        };
+
+        rules[TokenType::Tilde as usize] = ParseRule {
+            prefix: Some(Compiler::unary),
+            infix: None,
+            precedence: Precedence::None,
+        };
+
+        rules[TokenType::PlusPlus as usize].prefix = Some(Compiler::prefix_increment);
+        rules[TokenType::MinusMinus as usize].prefix = Some(Compiler::prefix_increment);
+
+        rules[TokenType::LeftBracket as usize] = ParseRule {
+            prefix: Some(Compiler::list),
+            infix: Some(Compiler::index),
+            precedence: Precedence::Call,
+        };
+
+        rules[TokenType::LeftBrace as usize].prefix = Some(Compiler::map);
+
+        rules[TokenType::Dot as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::dot),
+            precedence: Precedence::Call,
+        };
+
+        rules[TokenType::QuestionDot as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::optional_dot),
+            precedence: Precedence::Call,
+        };
+
+        rules[TokenType::Question as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::conditional),
+            precedence: Precedence::Conditional,
+        };
+
+        rules[TokenType::QuestionQuestion as usize] = ParseRule {
+            prefix: None,
+            infix: Some(Compiler::coalesce),
+            precedence: Precedence::Coalesce,
+        };
+
         rules[TokenType::Number as usize] = ParseRule {
             prefix: Some(Compiler::number),
             infix: None,
@@ -170,6 +388,12 @@ impl<'a> Compiler<'a> {
             precedence: Precedence::None,
         };
 
+        rules[TokenType::Interpolation as usize] = ParseRule {
+            prefix: Some(Compiler::interpolation),
+            infix: None,
+            precedence: Precedence::None,
+        };
+
         rules[TokenType::Identifier as usize] = ParseRule {
             prefix: Some(Compiler::variable),
             infix: None,
@@ -181,6 +405,12 @@ impl<'a> Compiler<'a> {
             scanner: Scanner::new("".to_string()),
             chunk,
             rules,
+            last_read: None,
+            locals: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+            tries: Vec::new(),
+            global_constants: HashMap::new(),
         }
     }
     pub fn compile(&mut self, source: String) -> bool {
@@ -207,11 +437,14 @@ impl<'a> Compiler<'a> {
         self.parser.previous = self.parser.current.clone();
         loop {
             self.parser.current = self.scanner.scan_token();
-            if self.parser.current.token_type != TokenType::Error {
-                break;
+            match self.parser.current.token_type {
+                // Doc comments are only of interest to tooling.
+                TokenType::DocComment => continue,
+                TokenType::Error => {}
+                _ => break,
             }
             let message = self.parser.current.lexeme.as_str();
-            self.error_at_current(&message);
+            self.error_at_current(message);
         }
     }
 
@@ -226,6 +459,7 @@ impl<'a> Compiler<'a> {
     fn end_compiler(&mut self) {
         self.emit_return();
 
+        #[cfg(feature = "debug_trace_execution")]
         if !*self.parser.had_error.borrow() {
             self.chunk.disassemble("code");
         }
@@ -237,18 +471,25 @@ impl<'a> Compiler<'a> {
     }
 
     fn unary(&mut self, _: bool) {
-        let operator_type = self.parser.previous.token_type.clone();
+        let operator_type = self.parser.previous.token_type;
 
         self.parse_precedence(Precedence::Unary);
 
         match operator_type {
             TokenType::Bang => self.emit_byte(OpCode::OpNot.into()),
             TokenType::Minus => self.emit_byte(OpCode::OpNegate.into()),
+            TokenType::Tilde => self.emit_byte(OpCode::OpBitNot.into()),
             _ => {}
         }
     }
     fn parse_precedence(&mut self, precedence: Precedence) {
         self.advance();
+        self.parse_from_previous(precedence);
+    }
+
+    // Like parse_precedence, but the expression's first token has already
+    // been consumed.
+    fn parse_from_previous(&mut self, precedence: Precedence) {
         if let Some(prefix_rule) = self.rules[self.parser.previous.token_type as usize].prefix {
             let can_assign = precedence <= Precedence::Assignment;
             prefix_rule(self, can_assign);
@@ -260,9 +501,16 @@ impl<'a> Compiler<'a> {
                     infix_rule(self, can_assign);
                 }
             }
+
+            // A place consumes its own `=`, `op=` and `++`, so any left over
+            // follow something that cannot be assigned, such as `a + b = c`.
+            let next = self.parser.current.token_type;
+            if (can_assign && is_assignment(next)) || is_increment(next) {
+                self.advance();
+                self.error("Invalid assignment target.");
+            }
         } else {
             self.error("Expect expression.");
-            return;
         }
     }
 
@@ -273,6 +521,8 @@ impl<'a> Compiler<'a> {
     fn declaration(&mut self) {
         if self.matches(TokenType::Var) {
             self.var_declaration();
+        } else if self.matches(TokenType::Const) {
+            self.const_declaration();
         } else {
             self.statement();
         }
@@ -282,12 +532,297 @@ impl<'a> Compiler<'a> {
     }
 
     fn named_variable(&mut self, name: &Token, can_assign: bool) {
-        let arg = self.identifier_constant(name);
+        let place = match self.resolve_local(name) {
+            Some(slot) => Place::Local(slot),
+            None => Place::Global(self.identifier_constant(name)),
+        };
+        let next = self.parser.current.token_type;
+        let writes = (can_assign && is_assignment(next)) || is_increment(next);
+        match self.constant_literal(place) {
+            Some(literal) if !writes => {
+                let start = self.chunk.code.len();
+                for byte in literal {
+                    self.emit_byte(byte);
+                }
+                self.last_read = Some((place, start, self.chunk.code.len()));
+            }
+            _ => self.place(place, can_assign),
+        }
+    }
+
+    // The name of the constant a place refers to, if it is one.
+    fn constant_name(&self, place: Place) -> Option<String> {
+        match place {
+            Place::Local(slot) => {
+                let local = &self.locals[slot as usize];
+                local.constant.then(|| local.name.clone())
+            }
+            Place::Global(arg) => {
+                let name = self.chunk.constants.values[arg as usize].to_string();
+                self.global_constants.contains_key(&name).then_some(name)
+            }
+            Place::Index => None,
+        }
+    }
+
+    fn constant_literal(&self, place: Place) -> Option<Vec<u8>> {
+        match place {
+            Place::Local(slot) => self.locals[slot as usize].literal.clone(),
+            Place::Global(arg) => {
+                let name = self.chunk.constants.values[arg as usize].to_string();
+                self.global_constants.get(&name).cloned().flatten()
+            }
+            Place::Index => None,
+        }
+    }
+
+    fn check_assignable(&self, place: Place) {
+        if let Some(name) = self.constant_name(place) {
+            self.error(&format!("Cannot assign to constant '{}'.", name));
+        }
+    }
+
+    fn resolve_local(&self, name: &Token) -> Option<u8> {
+        let slot = self.locals.iter().rposition(|local| local.name == name.lexeme)?;
+        if self.locals[slot].depth.is_none() {
+            self.error("Can't read local variable in its own initializer.");
+        }
+        Some(slot as u8)
+    }
+
+    // Compiles the rest of an expression that names a place: an assignment,
+    // a compound assignment, a postfix `++`/`--`, or else a plain read. A
+    // read is remembered so a prefix `++` can turn it back into a place.
+    fn place(&mut self, place: Place, can_assign: bool) {
+        let next = self.parser.current.token_type;
+        if (can_assign && is_assignment(next)) || is_increment(next) {
+            self.check_assignable(place);
+        }
         if can_assign && self.matches(TokenType::Equal) {
             self.expression();
-            self.emit_bytes(OpCode::OpSetGlobal.into(), arg);
+            self.emit_set(place);
+        } else if let Some(operator) = self.compound_assignment(can_assign) {
+            // x += e  =>  x = x + e
+            self.emit_get_for_update(place);
+            self.expression();
+            self.emit_byte(operator.into());
+            self.emit_set(place);
+        } else if let Some(operator) = self.postfix_increment() {
+            self.emit_postfix_increment(place, operator);
+        } else {
+            let start = self.chunk.code.len();
+            self.emit_get(place);
+            self.last_read = Some((place, start, self.chunk.code.len()));
+        }
+    }
+
+    fn emit_get(&mut self, place: Place) {
+        match place {
+            Place::Global(arg) => self.emit_bytes(OpCode::OpGetGlobal.into(), arg),
+            Place::Local(slot) => self.emit_bytes(OpCode::OpGetLocal.into(), slot),
+            Place::Index => self.emit_byte(OpCode::OpIndexGet.into()),
+        }
+    }
+
+    // Reads a place that is about to be written, keeping whatever the
+    // write will need. The list and index are evaluated only once.
+    fn emit_get_for_update(&mut self, place: Place) {
+        if let Place::Index = place {
+            self.emit_byte(OpCode::OpDupTwo.into());
+        }
+        self.emit_get(place);
+    }
+
+    // Stores the value on top of the stack, leaving it there.
+    fn emit_set(&mut self, place: Place) {
+        match place {
+            Place::Global(arg) => self.emit_bytes(OpCode::OpSetGlobal.into(), arg),
+            Place::Local(slot) => self.emit_bytes(OpCode::OpSetLocal.into(), slot),
+            Place::Index => self.emit_byte(OpCode::OpIndexSet.into()),
+        }
+    }
+
+    // `x++` leaves the old value. A variable is simply read twice. An indexed
+    // element's old value is tucked below the list and index, where it is
+    // left once the new value is stored.
+    fn emit_postfix_increment(&mut self, place: Place, operator: OpCode) {
+        match place {
+            Place::Global(_) | Place::Local(_) => {
+                self.emit_get(place);
+                self.emit_get(place);
+                self.emit_constant(Value::Int(1));
+                self.emit_byte(operator.into());
+                self.emit_set(place);
+                self.emit_byte(OpCode::OpPop.into());
+            }
+            Place::Index => {
+                self.emit_get_for_update(place);
+                self.emit_byte(OpCode::OpTuck.into());
+                self.emit_constant(Value::Int(1));
+                self.emit_byte(operator.into());
+                self.emit_set(place);
+                self.emit_byte(OpCode::OpPop.into());
+            }
+        }
+    }
+
+    // `++x` and `--x` leave the new value. The operand is compiled as an
+    // ordinary expression; if it turns out to be a plain read of a place,
+    // the read is taken back and replaced by the update. As with `=`, a
+    // parenthesized place does not count.
+    fn prefix_increment(&mut self, _: bool) {
+        let operator = match self.parser.previous.token_type {
+            TokenType::PlusPlus => OpCode::OpAdd,
+            _ => OpCode::OpSubtract,
+        };
+        let named = self.parser.current.token_type == TokenType::Identifier;
+        self.parse_precedence(Precedence::Call);
+
+        let (place, start) = match self.last_read.take() {
+            Some((place, start, end)) if named && end == self.chunk.code.len() => (place, start),
+            _ => return self.error("Invalid assignment target."),
+        };
+        self.check_assignable(place);
+        self.chunk.code.truncate(start);
+        self.chunk.lines.truncate(start);
+
+        self.emit_get_for_update(place);
+        self.emit_constant(Value::Int(1));
+        self.emit_byte(operator.into());
+        self.emit_set(place);
+    }
+
+    // Consumes a postfix `++` or `--`, returning the opcode it applies.
+    fn postfix_increment(&mut self) -> Option<OpCode> {
+        if self.matches(TokenType::PlusPlus) {
+            Some(OpCode::OpAdd)
+        } else if self.matches(TokenType::MinusMinus) {
+            Some(OpCode::OpSubtract)
         } else {
-            self.emit_bytes(OpCode::OpGetGlobal.into(), arg);
+            None
+        }
+    }
+
+    // Consumes a compound assignment operator such as `+=`, returning the
+    // opcode of its binary operator.
+    fn compound_assignment(&mut self, can_assign: bool) -> Option<OpCode> {
+        if !can_assign {
+            return None;
+        }
+        let operator = compound_operator(self.parser.current.token_type)?;
+        self.advance();
+        Some(operator)
+    }
+
+    // `[a, b, c]`, with an optional trailing comma.
+    fn list(&mut self, _: bool) {
+        let mut count = 0;
+        while self.parser.current.token_type != TokenType::RightBracket {
+            self.expression();
+            count += 1;
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBracket, "Expect ']' after list elements.");
+
+        match u8::try_from(count) {
+            Ok(count) => self.emit_bytes(OpCode::OpBuildList.into(), count),
+            Err(_) => self.error("Too many elements in list literal."),
+        }
+    }
+
+    // `{key: value, ...}`, with an optional trailing comma.
+    fn map(&mut self, _: bool) {
+        let mut count = 0;
+        while self.parser.current.token_type != TokenType::RightBrace {
+            self.expression();
+            self.consume(TokenType::Colon, "Expect ':' after map key.");
+            self.expression();
+            count += 1;
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after map entries.");
+
+        match u8::try_from(count) {
+            Ok(count) => self.emit_bytes(OpCode::OpBuildMap.into(), count),
+            Err(_) => self.error("Too many entries in map literal."),
+        }
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+        self.place(Place::Index, can_assign);
+    }
+
+    // `receiver.name(args)` calls a built-in method. There are no fields,
+    // so the parentheses are required.
+    fn dot(&mut self, _: bool) {
+        self.method_call();
+    }
+
+    // `receiver?.name(args)` is nil when the receiver is nil, and so is
+    // the rest of the chain: `a?.b().c()` skips both calls. Nothing in the
+    // chain can be assigned to.
+    fn optional_dot(&mut self, _: bool) {
+        let skip = self.emit_jump(OpCode::OpJumpIfNil);
+        self.method_call();
+        while Precedence::Call <= self.rules[self.parser.current.token_type as usize].precedence {
+            self.advance();
+            if let Some(infix_rule) = self.rules[self.parser.previous.token_type as usize].infix {
+                infix_rule(self, false);
+            }
+        }
+        self.patch_jump(skip);
+        // The chain ends in a jump target, which `++` must not cut into.
+        self.last_read = None;
+    }
+
+    // `cond ? a : b`, right-associative: `a ? b : c ? d : e` is
+    // `a ? b : (c ? d : e)`.
+    fn conditional(&mut self, _: bool) {
+        let else_jump = self.emit_jump(OpCode::OpJumpIfFalse);
+        self.parse_precedence(Precedence::Conditional);
+        let end_jump = self.emit_jump(OpCode::OpJump);
+        self.consume(TokenType::Colon, "Expect ':' after then branch of conditional expression.");
+        self.patch_jump(else_jump);
+        self.parse_precedence(Precedence::Conditional);
+        self.patch_jump(end_jump);
+    }
+
+    // `a ?? b` is `a` unless it is nil, in which case `b` is evaluated
+    // instead. Only nil counts; `false ?? b` is false.
+    fn coalesce(&mut self, _: bool) {
+        let end_jump = self.emit_jump(OpCode::OpJumpIfNotNil);
+        self.emit_byte(OpCode::OpPop.into());
+        self.parse_precedence(Precedence::Coalesce);
+        self.patch_jump(end_jump);
+    }
+
+    fn method_call(&mut self) {
+        self.consume(TokenType::Identifier, "Expect method name after '.'.");
+        let name = self.identifier_constant(&self.parser.previous.clone());
+        self.consume(TokenType::LeftParen, "Expect '(' after method name.");
+
+        let mut count = 0;
+        while self.parser.current.token_type != TokenType::RightParen {
+            self.expression();
+            count += 1;
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+
+        match u8::try_from(count) {
+            Ok(count) => {
+                self.emit_bytes(OpCode::OpInvoke.into(), name);
+                self.emit_byte(count);
+            }
+            Err(_) => self.error("Can't have more than 255 arguments."),
         }
     }
 
@@ -309,10 +844,87 @@ impl<'a> Compiler<'a> {
         self.define_variable(global);
     }
 
+    // `const NAME = value;`. Assigning to a constant is a compile error,
+    // or for a global assigned on a later REPL line, a runtime error. Reads
+    // of a constant initialized with a literal use the literal directly.
+    fn const_declaration(&mut self) {
+        let global = self.parse_variable("Expect constant name.");
+        let name = self.parser.previous.lexeme.clone();
+        self.consume(TokenType::Equal, "Expect '=' after constant name.");
+        let start = self.chunk.code.len();
+        self.expression();
+        let literal = self.literal_code(start);
+        self.consume(TokenType::Semicolon, "Expect ';' after constant declaration.");
+
+        if self.scope_depth > 0 {
+            self.mark_initialized();
+            if let Some(local) = self.locals.last_mut() {
+                local.constant = true;
+                local.literal = literal;
+            }
+            return;
+        }
+        self.global_constants.insert(name, literal);
+        self.emit_bytes(OpCode::OpDefineConstant.into(), global);
+    }
+
+    // The code from `start` on, if it is a single literal.
+    fn literal_code(&self, start: usize) -> Option<Vec<u8>> {
+        let code = &self.chunk.code[start..];
+        let opcode = OpCode::try_from(*code.first()?).ok()?;
+        let literal = matches!(
+            opcode,
+            OpCode::OpConstant | OpCode::OpNil | OpCode::OpTrue | OpCode::OpFalse
+        );
+        (literal && code.len() == opcode.width()).then(|| code.to_vec())
+    }
+
+    // Returns the name's constant for a global. Locals live on the stack
+    // and need no constant.
     fn parse_variable(&mut self, message: &str) -> u8 {
         self.consume(TokenType::Identifier, message);
+        if self.scope_depth > 0 {
+            self.declare_variable();
+            return 0;
+        }
         let token = self.parser.previous.clone();
-        return self.identifier_constant(&token);
+        if self.global_constants.contains_key(&token.lexeme) {
+            self.error(&format!("Constant '{}' is already defined.", token.lexeme));
+        }
+        self.identifier_constant(&token)
+    }
+
+    fn declare_variable(&mut self) {
+        let name = self.parser.previous.lexeme.clone();
+        let duplicate = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= self.scope_depth))
+            .any(|local| local.name == name);
+        if duplicate {
+            self.error("Already a variable with this name in this scope.");
+        }
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: String) {
+        if self.locals.len() > u8::MAX as usize {
+            self.error("Too many local variables.");
+            return;
+        }
+        self.locals.push(Local {
+            name,
+            depth: None,
+            constant: false,
+            literal: None,
+        });
+    }
+
+    fn mark_initialized(&mut self) {
+        if let Some(local) = self.locals.last_mut() {
+            local.depth = Some(self.scope_depth);
+        }
     }
 
     fn identifier_constant(&mut self, token: &Token) -> u8 {
@@ -320,6 +932,10 @@ impl<'a> Compiler<'a> {
     }
 
     fn define_variable(&mut self, global: u8) {
+        if self.scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
         self.emit_bytes(OpCode::OpDefineGlobal.into(), global);
     }
 
@@ -335,8 +951,13 @@ impl<'a> Compiler<'a> {
                 TokenType::Class
                 | TokenType::Fn
                 | TokenType::Var
+                | TokenType::Const
                 | TokenType::For
+                | TokenType::Break
+                | TokenType::Continue
                 | TokenType::If
+                | TokenType::Try
+                | TokenType::Throw
                 | TokenType::While
                 | TokenType::Print
                 | TokenType::Return => return,
@@ -349,11 +970,526 @@ impl<'a> Compiler<'a> {
     fn statement(&mut self) {
         if self.matches(TokenType::Print) {
             self.print_statement();
+        } else if self.matches(TokenType::For) {
+            self.for_statement(None);
+        } else if self.matches(TokenType::Match) {
+            self.match_statement();
+        } else if self.matches(TokenType::Try) {
+            self.try_statement();
+        } else if self.matches(TokenType::Throw) {
+            self.throw_statement();
+        } else if self.matches(TokenType::Break) {
+            self.break_statement();
+        } else if self.matches(TokenType::Continue) {
+            self.continue_statement();
+        } else if self.matches(TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else if self.matches(TokenType::Identifier) {
+            // `name: for ...` labels a loop; anything else is an expression
+            // that starts with the identifier.
+            let label = self.parser.previous.lexeme.clone();
+            if self.matches(TokenType::Colon) {
+                if self.matches(TokenType::For) {
+                    self.for_statement(Some(label));
+                } else {
+                    self.error_at_current("Expect 'for' after label.");
+                }
+            } else {
+                self.parse_from_previous(Precedence::Assignment);
+                self.consume(TokenType::Semicolon, "Expect ; after Expression");
+                self.emit_byte(OpCode::OpPop.into());
+            }
         } else {
             self.expression_statement();
         }
     }
 
+    fn break_statement(&mut self) {
+        let Some(index) = self.target_loop("break") else {
+            return;
+        };
+        self.emit_exit(index, ExitKind::Break);
+        self.consume(TokenType::Semicolon, "Expect ';' after 'break'.");
+    }
+
+    fn continue_statement(&mut self) {
+        let Some(index) = self.target_loop("continue") else {
+            return;
+        };
+        self.emit_exit(index, ExitKind::Continue);
+        self.consume(TokenType::Semicolon, "Expect ';' after 'continue'.");
+    }
+
+    // The loop a `break` or `continue` applies to: the one named by an
+    // optional label, or else the innermost.
+    fn target_loop(&mut self, keyword: &str) -> Option<usize> {
+        if self.matches(TokenType::Identifier) {
+            let label = &self.parser.previous.lexeme;
+            let index = self
+                .loops
+                .iter()
+                .rposition(|enclosing| enclosing.label.as_ref() == Some(label));
+            if index.is_none() {
+                self.error(&format!("No enclosing loop labeled '{}'.", label));
+            }
+            return index;
+        }
+        if self.loops.is_empty() {
+            self.error(&format!("Can't use '{}' outside of a loop.", keyword));
+            return None;
+        }
+        Some(self.loops.len() - 1)
+    }
+
+    // Jumps out to the loop at `index`. If a try statement lies in
+    // between, the jump goes to the end of the innermost one instead,
+    // which passes it on once its `finally` block has run.
+    fn emit_exit(&mut self, index: usize, kind: ExitKind) {
+        if let Some(depth) = self
+            .tries
+            .last()
+            .filter(|enclosing| enclosing.loops > index)
+            .map(|enclosing| enclosing.depth)
+        {
+            self.emit_pops(depth);
+            let jump = self.emit_jump(OpCode::OpJump);
+            let exit = Exit { index, kind, jump };
+            self.tries.last_mut().unwrap().exits.push(exit);
+            return;
+        }
+        match kind {
+            ExitKind::Break => {
+                self.emit_pops(self.loops[index].break_locals);
+                let jump = self.emit_jump(OpCode::OpJump);
+                self.loops[index].breaks.push(jump);
+            }
+            ExitKind::Continue => {
+                self.emit_pops(self.loops[index].continue_locals);
+                self.emit_loop(self.loops[index].start);
+            }
+        }
+    }
+
+    // Pops the locals above the first `keep`, leaving them declared: the
+    // code after a jump still sees them.
+    fn emit_pops(&mut self, keep: usize) {
+        for _ in keep..self.locals.len() {
+            self.emit_byte(OpCode::OpPop.into());
+        }
+    }
+
+    fn block(&mut self) {
+        while !matches!(
+            self.parser.current.token_type,
+            TokenType::RightBrace | TokenType::EOF
+        ) {
+            self.declaration();
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.");
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.scope_depth -= 1;
+        while self
+            .locals
+            .last()
+            .is_some_and(|local| local.depth.is_none_or(|depth| depth > self.scope_depth))
+        {
+            self.emit_byte(OpCode::OpPop.into());
+            self.locals.pop();
+        }
+    }
+
+    // `for name in iterable { ... }`. The iterator sits in a hidden local
+    // below the loop variable. OpForIter pushes each value in turn; once
+    // the iterator is done it pushes nil in the loop variable's place and
+    // jumps past the body. A `break` leaves the loop variable in place and
+    // jumps to the same spot.
+    fn for_statement(&mut self, label: Option<String>) {
+        self.begin_scope();
+        self.consume(TokenType::Identifier, "Expect loop variable name after 'for'.");
+        let name = self.parser.previous.lexeme.clone();
+        self.consume(TokenType::In, "Expect 'in' after loop variable.");
+        self.expression();
+        self.emit_byte(OpCode::OpGetIter.into());
+        // No identifier is empty, so the iterator cannot be named.
+        self.add_local(String::new());
+        self.mark_initialized();
+
+        let loop_start = self.chunk.code.len();
+        let exit = self.emit_jump(OpCode::OpForIter);
+
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized();
+        self.loops.push(Loop {
+            label,
+            start: loop_start,
+            continue_locals: self.locals.len() - 1,
+            break_locals: self.locals.len(),
+            breaks: Vec::new(),
+        });
+        self.consume(TokenType::LeftBrace, "Expect '{' before loop body.");
+        self.block();
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit);
+        for jump in self.loops.pop().unwrap().breaks {
+            self.patch_jump(jump);
+        }
+        self.emit_byte(OpCode::OpPop.into());
+        self.end_scope();
+    }
+
+    // `try { ... } catch (name) { ... } finally { ... }`, with `catch`,
+    // `finally` or both. An error in the try block is bound to the catch
+    // variable. The finally block runs with a hidden local holding the
+    // error it must re-raise afterwards, nil if the try or catch block
+    // finished normally, or the index of the `break` or `continue` that
+    // left it. Handlers are added once the statement is done, so that
+    // those of nested statements come first.
+    fn try_statement(&mut self) {
+        let depth = self.locals.len();
+        self.tries.push(Try {
+            loops: self.loops.len(),
+            depth,
+            exits: Vec::new(),
+        });
+
+        let try_start = self.chunk.code.len();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'try'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        let try_end = self.chunk.code.len();
+
+        let mut handlers = Vec::new();
+        // The code an error in the catch block unwinds to.
+        let mut catch_range = None;
+        if self.matches(TokenType::Catch) {
+            let skip_catch = self.emit_jump(OpCode::OpJump);
+            let catch_start = self.chunk.code.len();
+            handlers.push(Handler {
+                start: try_start,
+                end: try_end,
+                target: catch_start,
+                depth,
+            });
+
+            self.consume(TokenType::LeftParen, "Expect '(' after 'catch'.");
+            self.consume(TokenType::Identifier, "Expect error variable name.");
+            let name = self.parser.previous.lexeme.clone();
+            self.consume(TokenType::RightParen, "Expect ')' after error variable.");
+            self.consume(TokenType::LeftBrace, "Expect '{' after catch clause.");
+            self.begin_scope();
+            self.add_local(name);
+            self.mark_initialized();
+            self.block();
+            self.end_scope();
+
+            catch_range = Some((catch_start, self.chunk.code.len()));
+            self.patch_jump(skip_catch);
+        } else if self.parser.current.token_type != TokenType::Finally {
+            self.error_at_current("Expect 'catch' or 'finally' after try block.");
+        }
+
+        let exits = self.tries.pop().unwrap().exits;
+        let mut finally_start = None;
+        if self.matches(TokenType::Finally) {
+            // Finishing normally leaves nil for the finally block. Errors
+            // in the try block, if not caught, or in the catch block leave
+            // the error instead.
+            self.emit_byte(OpCode::OpNil.into());
+            let target = self.chunk.code.len();
+            let (start, end) = match catch_range {
+                Some(range) => range,
+                None => (try_start, try_end),
+            };
+            handlers.push(Handler {
+                start,
+                end,
+                target,
+                depth,
+            });
+            self.finally_block(&exits);
+            finally_start = Some(target);
+        }
+
+        // The exits land here, past the normal path, with only the locals
+        // from before the statement on the stack.
+        if !exits.is_empty() {
+            let over = self.emit_jump(OpCode::OpJump);
+            for (number, exit) in exits.iter().enumerate() {
+                self.patch_jump(exit.jump);
+                match finally_start {
+                    Some(start) => {
+                        self.emit_constant(Value::Int(number as i64));
+                        self.emit_loop(start);
+                    }
+                    None => self.emit_exit(exit.index, exit.kind),
+                }
+            }
+            self.patch_jump(over);
+        }
+
+        self.chunk.handlers.extend(handlers);
+    }
+
+    // The pending error sits in a hidden local below the block's own
+    // locals; OpEndFinally pops it, so the local is dropped without
+    // emitting a pop. A `break` or `continue` that left the try statement
+    // arrives with its index in the local instead, and is sent on to its
+    // loop after the block.
+    fn finally_block(&mut self, exits: &[Exit]) {
+        self.begin_scope();
+        self.add_local(String::new());
+        self.mark_initialized();
+        self.consume(TokenType::LeftBrace, "Expect '{' after 'finally'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+        let pending = (self.locals.len() - 1) as u8;
+        for (number, exit) in exits.iter().enumerate() {
+            self.emit_bytes(OpCode::OpGetLocal.into(), pending);
+            self.emit_constant(Value::Int(number as i64));
+            self.emit_byte(OpCode::OpEqual.into());
+            let next = self.emit_jump(OpCode::OpJumpIfFalse);
+            self.emit_exit(exit.index, exit.kind);
+            self.patch_jump(next);
+        }
+        self.locals.pop();
+        self.scope_depth -= 1;
+        self.emit_byte(OpCode::OpEndFinally.into());
+    }
+
+    // `throw value;`, where the value is an error to rethrow or the
+    // message for a new one.
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.");
+        self.emit_byte(OpCode::OpThrow.into());
+    }
+
+    // `match value { pattern => statement, ... }`. The value is kept in a
+    // hidden local. Each arm tests its pattern against it, jumping to the
+    // next arm on the first failed test, then binds the pattern's names as
+    // locals and checks its guard. Only the first matching arm runs; a
+    // value that matches no arm is ignored.
+    fn match_statement(&mut self) {
+        let line = self.parser.previous.line;
+        self.begin_scope();
+        self.expression();
+        self.add_local(String::new());
+        self.mark_initialized();
+        let subject = (self.locals.len() - 1) as u8;
+        self.consume(TokenType::LeftBrace, "Expect '{' after match value.");
+
+        let mut exits = Vec::new();
+        let mut exhaustive = false;
+        while !matches!(
+            self.parser.current.token_type,
+            TokenType::RightBrace | TokenType::EOF
+        ) {
+            let pattern = self.pattern(&mut Vec::new());
+            let guarded = self.parser.current.token_type == TokenType::If;
+            exhaustive |= !guarded && pattern.is_irrefutable();
+
+            let mut fails = Vec::new();
+            self.emit_test(&pattern, subject, &mut Vec::new(), &mut fails);
+
+            self.begin_scope();
+            let mut bindings = Vec::new();
+            collect_bindings(&pattern, &mut Vec::new(), &mut bindings);
+            for (name, path) in &bindings {
+                self.emit_path(subject, path);
+                self.add_local(name.clone());
+                self.mark_initialized();
+            }
+            let guard = if self.matches(TokenType::If) {
+                self.expression();
+                Some(self.emit_jump(OpCode::OpJumpIfFalse))
+            } else {
+                None
+            };
+
+            self.consume(TokenType::EqualGreater, "Expect '=>' after pattern.");
+            self.statement();
+            self.end_scope();
+            exits.push(self.emit_jump(OpCode::OpJump));
+
+            // A failed guard drops the bindings before trying the next arm.
+            if let Some(guard) = guard {
+                self.patch_jump(guard);
+                for _ in &bindings {
+                    self.emit_byte(OpCode::OpPop.into());
+                }
+            }
+            for jump in fails {
+                self.patch_jump(jump);
+            }
+            self.matches(TokenType::Comma);
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after match arms.");
+
+        for jump in exits {
+            self.patch_jump(jump);
+        }
+        self.end_scope();
+
+        if !exhaustive && !*self.parser.had_error.borrow() {
+            self.warning(line, "match has no wildcard arm; unmatched values are ignored.");
+        }
+    }
+
+    // `a | b | c`, or a single pattern. `names` collects the bindings made
+    // so far in the arm, to catch duplicates.
+    fn pattern(&mut self, names: &mut Vec<String>) -> Pattern {
+        let first = self.single_pattern(names);
+        if self.parser.current.token_type != TokenType::Pipe {
+            return first;
+        }
+        let mut alternatives = vec![first];
+        while self.matches(TokenType::Pipe) {
+            alternatives.push(self.single_pattern(names));
+        }
+        if alternatives.iter().any(Pattern::binds) {
+            self.error("Alternative patterns can't bind variables.");
+        }
+        Pattern::Alternatives(alternatives)
+    }
+
+    fn single_pattern(&mut self, names: &mut Vec<String>) -> Pattern {
+        self.advance();
+        match self.parser.previous.token_type {
+            TokenType::Identifier if self.parser.previous.lexeme == "_" => Pattern::Wildcard,
+            TokenType::Identifier => {
+                let name = self.parser.previous.lexeme.clone();
+                if names.contains(&name) {
+                    self.error(&format!("Duplicate binding '{}' in pattern.", name));
+                }
+                names.push(name.clone());
+                Pattern::Binding(name)
+            }
+            TokenType::LeftBracket => {
+                let mut items = Vec::new();
+                while self.parser.current.token_type != TokenType::RightBracket {
+                    items.push(self.pattern(names));
+                    if !self.matches(TokenType::Comma) {
+                        break;
+                    }
+                }
+                self.consume(TokenType::RightBracket, "Expect ']' after list pattern.");
+                if items.len() > u8::MAX as usize {
+                    self.error("Too many elements in list pattern.");
+                }
+                Pattern::List(items)
+            }
+            TokenType::Number => Pattern::Literal(self.number_literal().unwrap_or(Value::Nil)),
+            TokenType::Minus => {
+                self.consume(TokenType::Number, "Expect number after '-' in pattern.");
+                let value = self.number_literal().unwrap_or(Value::Nil);
+                Pattern::Literal((-value).unwrap_or(Value::Nil))
+            }
+            TokenType::String => Pattern::Literal(Value::Str(self.parser.previous.lexeme.clone())),
+            TokenType::True => Pattern::Literal(Value::Boolean(true)),
+            TokenType::False => Pattern::Literal(Value::Boolean(false)),
+            TokenType::Nil => Pattern::Literal(Value::Nil),
+            _ => {
+                self.error("Expect pattern.");
+                Pattern::Wildcard
+            }
+        }
+    }
+
+    // Emits the checks a pattern makes on the value at `path` inside the
+    // subject, adding a jump to `fails` for each one.
+    fn emit_test(
+        &mut self,
+        pattern: &Pattern,
+        subject: u8,
+        path: &mut Vec<usize>,
+        fails: &mut Vec<usize>,
+    ) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => {}
+            Pattern::Literal(value) => {
+                self.emit_path(subject, path);
+                self.emit_constant(value.clone());
+                self.emit_byte(OpCode::OpEqual.into());
+                fails.push(self.emit_jump(OpCode::OpJumpIfFalse));
+            }
+            Pattern::List(items) => {
+                self.emit_path(subject, path);
+                self.emit_bytes(OpCode::OpMatchList.into(), items.len() as u8);
+                fails.push(self.emit_jump(OpCode::OpJumpIfFalse));
+                for (index, item) in items.iter().enumerate() {
+                    path.push(index);
+                    self.emit_test(item, subject, path, fails);
+                    path.pop();
+                }
+            }
+            // Each alternative but the last jumps to the next one when it
+            // fails, and past the rest when it matches.
+            Pattern::Alternatives(alternatives) => {
+                let (last, rest) = alternatives.split_last().unwrap();
+                let mut matched = Vec::new();
+                for alternative in rest {
+                    let mut next = Vec::new();
+                    self.emit_test(alternative, subject, path, &mut next);
+                    matched.push(self.emit_jump(OpCode::OpJump));
+                    for jump in next {
+                        self.patch_jump(jump);
+                    }
+                }
+                self.emit_test(last, subject, path, fails);
+                for jump in matched {
+                    self.patch_jump(jump);
+                }
+            }
+        }
+    }
+
+    // Pushes `subject[path[0]][path[1]]...`.
+    fn emit_path(&mut self, subject: u8, path: &[usize]) {
+        self.emit_bytes(OpCode::OpGetLocal.into(), subject);
+        for &index in path {
+            self.emit_constant(Value::Int(index as i64));
+            self.emit_byte(OpCode::OpIndexGet.into());
+        }
+    }
+
+    // Emits a jump with a placeholder distance, returning where the
+    // distance goes.
+    fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        self.emit_byte(instruction.into());
+        self.emit_bytes(0xff, 0xff);
+        self.chunk.code.len() - 2
+    }
+
+    // Points the jump at `at` to the next instruction emitted.
+    fn patch_jump(&mut self, at: usize) {
+        let Ok(distance) = u16::try_from(self.chunk.code.len() - at - 2) else {
+            return self.error("Too much code to jump over.");
+        };
+        self.chunk.code[at..at + 2].copy_from_slice(&distance.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_byte(OpCode::OpLoop.into());
+        let Ok(distance) = u16::try_from(self.chunk.code.len() + 2 - loop_start) else {
+            return self.error("Loop body too large.");
+        };
+        let [high, low] = distance.to_be_bytes();
+        self.emit_bytes(high, low);
+    }
+
     fn expression_statement(&mut self) {
         self.expression();
         self.consume(TokenType::Semicolon, "Expect ; after Expression");
@@ -367,8 +1503,37 @@ impl<'a> Compiler<'a> {
     }
 
     fn number(&mut self, _: bool) {
-        let value = self.parser.previous.lexeme.parse::<f64>().unwrap();
-        self.emit_constant(Value::Number(value));
+        if let Some(value) = self.number_literal() {
+            self.emit_constant(value);
+        }
+    }
+
+    fn number_literal(&mut self) -> Option<Value> {
+        // The scanner has already checked the literal's shape, so the only
+        // failure left is a float too large to represent. Literals with a
+        // fraction or exponent are floats; everything else is an integer of
+        // whatever size it needs.
+        let digits = self.parser.previous.lexeme.replace('_', "");
+        let radix = match digits.get(..2).map(|p| p.to_ascii_lowercase()).as_deref() {
+            Some("0x") => 16,
+            Some("0b") => 2,
+            Some("0o") => 8,
+            _ => 10,
+        };
+        let value = match radix {
+            10 if digits.contains(['.', 'e', 'E']) => digits
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .map(Value::Number),
+            10 => BigInt::parse(&digits, 10).map(Value::integer),
+            _ => BigInt::parse(&digits[2..], radix).map(Value::integer),
+        };
+
+        if value.is_none() {
+            self.error("Number literal is too large.");
+        }
+        value
     }
 
     fn literal(&mut self, _: bool) {
@@ -376,16 +1541,48 @@ impl<'a> Compiler<'a> {
             TokenType::False => self.emit_byte(OpCode::OpFalse.into()),
             TokenType::True => self.emit_byte(OpCode::OpTrue.into()),
             TokenType::Nil => self.emit_byte(OpCode::OpNil.into()),
-            _ => return,
+            _ => (),
         }
     }
 
     fn string(&mut self, _: bool) {
-        let len = self.parser.previous.lexeme.len() - 1;
-        let string = self.parser.previous.lexeme[1..len].to_string();
+        let string = self.parser.previous.lexeme.clone();
         self.emit_constant(Value::Str(string));
     }
 
+    // "a ${x} b ${y} c" arrives as Interpolation("a ") x Interpolation(" b ")
+    // y String(" c"). Every non-empty segment and expression is pushed and
+    // a single OpBuildString joins them, formatting values with Display.
+    fn interpolation(&mut self, _: bool) {
+        let mut parts = 0;
+        loop {
+            let segment = self.parser.previous.lexeme.clone();
+            if !segment.is_empty() {
+                self.emit_constant(Value::Str(segment));
+                parts += 1;
+            }
+
+            self.expression();
+            parts += 1;
+
+            if self.matches(TokenType::Interpolation) {
+                continue;
+            }
+            self.consume(TokenType::String, "Expect '}' after interpolated expression.");
+            let segment = self.parser.previous.lexeme.clone();
+            if !segment.is_empty() {
+                self.emit_constant(Value::Str(segment));
+                parts += 1;
+            }
+            break;
+        }
+
+        match u8::try_from(parts) {
+            Ok(parts) => self.emit_bytes(OpCode::OpBuildString.into(), parts),
+            Err(_) => self.error("Too many parts in string interpolation."),
+        }
+    }
+
     fn emit_constant(&mut self, value: Value) {
         let constant = self.make_constant(value);
         self.emit_bytes(OpCode::OpConstant.into(), constant);
@@ -409,30 +1606,57 @@ impl<'a> Compiler<'a> {
         }
         self.parser.panic_mode.replace(true);
 
-        eprintln!("[line {}] Error", token.line);
+        let mut report = format!("[line {}] Error", token.line);
         if token.token_type == TokenType::EOF {
-            eprint!(" at end");
+            report.push_str(" at end");
         } else if token.token_type == TokenType::Error {
             // Nothing.
         } else {
-            eprint!(" at {}", token.lexeme);
+            report.push_str(&format!(" at {}", token.lexeme));
         }
 
-        eprintln!(": {}", message);
+        report.push_str(&format!(": {}", message));
+        self.report(report);
         self.parser.had_error.replace(true);
     }
 
+    // Warnings are reported like errors but do not stop compilation.
+    fn warning(&self, line: usize, message: &str) {
+        self.report(format!("[line {}] Warning: {}", line, message));
+    }
+
+    fn report(&self, diagnostic: String) {
+        eprintln!("{}", diagnostic);
+        self.parser.diagnostics.borrow_mut().push(diagnostic);
+    }
+
     fn binary(&mut self, _: bool) {
-        let operator_type = self.parser.previous.token_type.clone();
-        let rule = self.rules[operator_type as usize].clone();
+        let operator_type = self.parser.previous.token_type;
+        let rule = self.rules[operator_type as usize];
 
-        self.parse_precedence(rule.precedence.next());
+        // `**` is right-associative and its right operand may be negated:
+        // `2 ** -1`, `2 ** 3 ** 2 == 2 ** 9`.
+        if operator_type == TokenType::StarStar {
+            self.parse_precedence(Precedence::Unary);
+        } else {
+            self.parse_precedence(rule.precedence.next());
+        }
 
         match operator_type {
             TokenType::Plus => self.emit_byte(OpCode::OpAdd.into()),
             TokenType::Minus => self.emit_byte(OpCode::OpSubtract.into()),
             TokenType::Star => self.emit_byte(OpCode::OpMultiply.into()),
             TokenType::Slash => self.emit_byte(OpCode::OpDivide.into()),
+            TokenType::Percent => self.emit_byte(OpCode::OpModulo.into()),
+            TokenType::SlashSlash => self.emit_byte(OpCode::OpFloorDivide.into()),
+            TokenType::StarStar => self.emit_byte(OpCode::OpPower.into()),
+            TokenType::Ampersand => self.emit_byte(OpCode::OpBitAnd.into()),
+            TokenType::Pipe => self.emit_byte(OpCode::OpBitOr.into()),
+            TokenType::Caret => self.emit_byte(OpCode::OpBitXor.into()),
+            TokenType::LessLess => self.emit_byte(OpCode::OpShiftLeft.into()),
+            TokenType::GreaterGreater => self.emit_byte(OpCode::OpShiftRight.into()),
+            TokenType::DotDot => self.emit_byte(OpCode::OpRange.into()),
+            TokenType::DotDotEqual => self.emit_byte(OpCode::OpRangeInclusive.into()),
             TokenType::Greater => self.emit_byte(OpCode::OpGreater.into()),
             TokenType::EqualEqual => self.emit_byte(OpCode::OpEqual.into()),
             TokenType::Less => self.emit_byte(OpCode::OpLess.into()),
@@ -461,6 +1685,50 @@ impl<'a> Compiler<'a> {
     }
 }
 
+// The binary operator a compound assignment token applies, such as OpAdd
+// for `+=`.
+fn compound_operator(token_type: TokenType) -> Option<OpCode> {
+    let operator = match token_type {
+        TokenType::PlusEqual => OpCode::OpAdd,
+        TokenType::MinusEqual => OpCode::OpSubtract,
+        TokenType::StarEqual => OpCode::OpMultiply,
+        TokenType::SlashEqual => OpCode::OpDivide,
+        TokenType::PercentEqual => OpCode::OpModulo,
+        TokenType::StarStarEqual => OpCode::OpPower,
+        TokenType::SlashSlashEqual => OpCode::OpFloorDivide,
+        TokenType::AmpersandEqual => OpCode::OpBitAnd,
+        TokenType::PipeEqual => OpCode::OpBitOr,
+        TokenType::CaretEqual => OpCode::OpBitXor,
+        TokenType::LessLessEqual => OpCode::OpShiftLeft,
+        TokenType::GreaterGreaterEqual => OpCode::OpShiftRight,
+        _ => return None,
+    };
+    Some(operator)
+}
+
+// The names a pattern binds, each with the index path to its value.
+fn collect_bindings(pattern: &Pattern, path: &mut Vec<usize>, out: &mut Vec<(String, Vec<usize>)>) {
+    match pattern {
+        Pattern::Binding(name) => out.push((name.clone(), path.clone())),
+        Pattern::List(items) => {
+            for (index, item) in items.iter().enumerate() {
+                path.push(index);
+                collect_bindings(item, path, out);
+                path.pop();
+            }
+        }
+        Pattern::Wildcard | Pattern::Literal(_) | Pattern::Alternatives(_) => {}
+    }
+}
+
+fn is_assignment(token_type: TokenType) -> bool {
+    token_type == TokenType::Equal || compound_operator(token_type).is_some()
+}
+
+fn is_increment(token_type: TokenType) -> bool {
+    matches!(token_type, TokenType::PlusPlus | TokenType::MinusMinus)
+}
+
 impl Precedence {
     fn next(self) -> Self {
         if self == Precedence::Primary {
@@ -469,12 +1737,269 @@ impl Precedence {
         let p = self as usize;
         (p + 1).into()
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The errors and warnings from compiling `source`.
+    fn diagnostics(source: &str) -> Vec<String> {
+        let mut chunk = Chunk::new();
+        let mut compiler = Compiler::new(&mut chunk);
+        compiler.compile(source.to_string());
+        compiler.parser.diagnostics.take()
+    }
 
-    fn previous(self) -> Self {
-        if self == Precedence::None {
-            panic!("no previous before None");
+    #[test]
+    fn reports_empty_interpolations() {
+        for source in [
+            "print \"a ${} b\";",
+            "print \"${ }\";",
+            "print \"${}${1}\";",
+        ] {
+            assert_eq!(
+                diagnostics(source),
+                ["[line 1] Error at }: Expect expression."],
+                "{}",
+                source
+            );
         }
-        let p = self as usize;
-        (p - 1).into()
+        assert_eq!(
+            diagnostics("print \"${1}${\"${2}\"}\";"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn reports_invalid_assignment_targets() {
+        for (source, at) in [
+            ("a + b = c;", "="),
+            ("1 = 2;", "="),
+            ("(a) = 1;", "="),
+            ("-a = 1;", "="),
+            ("a++ = 1;", "="),
+            ("a.len() = 1;", "="),
+            ("a + b += 1;", "+="),
+            ("\"x\" += 1;", "+="),
+            ("++1;", "1"),
+            ("++(a);", ")"),
+            ("1++;", "++"),
+            ("(a)++;", "++"),
+            ("(a[0])++;", "++"),
+            ("++a++;", "++"),
+        ] {
+            let source = format!("var a; var b; var c; {}", source);
+            assert_eq!(
+                diagnostics(&source),
+                [format!(
+                    "[line 1] Error at {}: Invalid assignment target.",
+                    at
+                )],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn accepts_assignable_places() {
+        let source = "var a = [1]; var b = 2; a[0] = b = 3; a[0] += b; ++a[0]; a[0]--; a + b++;";
+        assert_eq!(diagnostics(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_exits_without_a_loop() {
+        for (source, error) in [
+            (
+                "break;",
+                "Error at break: Can't use 'break' outside of a loop.",
+            ),
+            (
+                "continue;",
+                "Error at continue: Can't use 'continue' outside of a loop.",
+            ),
+            (
+                "match 1 { _ => break; }",
+                "Error at break: Can't use 'break' outside of a loop.",
+            ),
+            (
+                "for i in 0..1 { break nope; }",
+                "Error at nope: No enclosing loop labeled 'nope'.",
+            ),
+            (
+                "a: for i in 0..1 {} for j in 0..1 { continue a; }",
+                "Error at a: No enclosing loop labeled 'a'.",
+            ),
+            ("a: print 1;", "Error at print: Expect 'for' after label."),
+        ] {
+            assert_eq!(
+                diagnostics(source),
+                [format!("[line 1] {}", error)],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn warns_about_matches_without_a_wildcard() {
+        let warning = "[line 1] Warning: match has no wildcard arm; unmatched values are ignored.";
+        for source in [
+            "match 1 { 1 => print 1; }",
+            "match 1 { x if x > 0 => print x; }",
+            "match [1] { [_] => print 1; }",
+        ] {
+            assert_eq!(diagnostics(source), [warning], "{}", source);
+        }
+        for source in [
+            "match 1 { 1 => print 1; _ => print 2; }",
+            "match 1 { x => print x; }",
+            "match 1 { 1 | _ => print 1; }",
+        ] {
+            assert_eq!(diagnostics(source), Vec::<String>::new(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn reports_bad_patterns() {
+        for (source, error) in [
+            (
+                "match [1] { [x, x] => nil; _ => nil; }",
+                "Error at x: Duplicate binding 'x' in pattern.",
+            ),
+            (
+                "match 1 { 1 | x => nil; _ => nil; }",
+                "Error at x: Alternative patterns can't bind variables.",
+            ),
+            (
+                "match 1 { + => nil; _ => nil; }",
+                "Error at +: Expect pattern.",
+            ),
+            (
+                "match 1 { -x => nil; _ => nil; }",
+                "Error at x: Expect number after '-' in pattern.",
+            ),
+            (
+                "match 1 { 1 print 1; _ => nil; }",
+                "Error at print: Expect '=>' after pattern.",
+            ),
+        ] {
+            assert_eq!(
+                diagnostics(source),
+                [format!("[line 1] {}", error)],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn reports_bad_conditionals_and_optional_calls() {
+        for (source, error) in [
+            (
+                "print true ? 1;",
+                "Error at ;: Expect ':' after then branch of conditional expression.",
+            ),
+            (
+                "var n; n?.len;",
+                "Error at ;: Expect '(' after method name.",
+            ),
+            (
+                "var n; n?.len() = 1;",
+                "Error at =: Invalid assignment target.",
+            ),
+            (
+                "var n; n?.len()++;",
+                "Error at ++: Invalid assignment target.",
+            ),
+            (
+                "var n; ++n?.len();",
+                "Error at ): Invalid assignment target.",
+            ),
+        ] {
+            assert_eq!(
+                diagnostics(source),
+                [format!("[line 1] {}", error)],
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn reports_changes_to_constants() {
+        for (source, error) in [
+            (
+                "const x = 1; x = 2;",
+                "Error at x: Cannot assign to constant 'x'.",
+            ),
+            (
+                "const x = 1; x += 2;",
+                "Error at x: Cannot assign to constant 'x'.",
+            ),
+            (
+                "const x = 1; x++;",
+                "Error at x: Cannot assign to constant 'x'.",
+            ),
+            (
+                "const x = 1; --x;",
+                "Error at x: Cannot assign to constant 'x'.",
+            ),
+            (
+                "{ const y = [1]; y = 2; }",
+                "Error at y: Cannot assign to constant 'y'.",
+            ),
+            (
+                "{ const y = 1; { y = 2; } }",
+                "Error at y: Cannot assign to constant 'y'.",
+            ),
+            (
+                "const x = 1; const x = 2;",
+                "Error at x: Constant 'x' is already defined.",
+            ),
+            (
+                "const x = 1; var x = 2;",
+                "Error at x: Constant 'x' is already defined.",
+            ),
+            ("const x;", "Error at ;: Expect '=' after constant name."),
+        ] {
+            assert_eq!(
+                diagnostics(source),
+                [format!("[line 1] {}", error)],
+                "{}",
+                source
+            );
+        }
+        // Only the binding is constant, not the list it holds.
+        assert_eq!(
+            diagnostics("const xs = [1]; xs[0] = 2; xs.push(3);"),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn inlines_literal_constants() {
+        let mut chunk = Chunk::new();
+        let source =
+            "const a = 1.5; const b = \"s\"; { const c = nil; print a; print b; print c; }";
+        assert!(!Compiler::new(&mut chunk).compile(source.to_string()));
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let opcode = OpCode::try_from(chunk.code[offset]).unwrap();
+            assert!(
+                !matches!(opcode, OpCode::OpGetGlobal | OpCode::OpGetLocal),
+                "{:?} at {}",
+                opcode,
+                offset
+            );
+            offset += opcode.width();
+        }
+
+        // A constant that is not a single literal is read like a variable.
+        let mut chunk = Chunk::new();
+        let source = "{ const d = 1 + 2; print d; }";
+        assert!(!Compiler::new(&mut chunk).compile(source.to_string()));
+        assert!(chunk.code.contains(&OpCode::OpGetLocal.into()));
     }
 }
@@ -0,0 +1,90 @@
+use std::fmt::{Display, Formatter};
+
+// What kind of runtime error occurred. Scripts see it as `e.kind()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    // An operand or argument of the wrong type, or a missing method.
+    Type,
+    // Division by zero, or an integer result too large to build.
+    Arithmetic,
+    // An index outside a list or string.
+    Index,
+    // A key missing from a map.
+    Key,
+    // An undefined variable, or an assignment to a constant.
+    Name,
+    // An argument of the right type but an unusable value.
+    Value,
+    // A value raised by `throw`.
+    Thrown,
+}
+
+impl ErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Type => "TypeError",
+            ErrorKind::Arithmetic => "ArithmeticError",
+            ErrorKind::Index => "IndexError",
+            ErrorKind::Key => "KeyError",
+            ErrorKind::Name => "NameError",
+            ErrorKind::Value => "ValueError",
+            ErrorKind::Thrown => "Error",
+        }
+    }
+}
+
+// A failed operation. The VM turns it into an error value and unwinds to
+// the nearest `catch`.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl RuntimeError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+pub fn type_error(message: impl Into<String>) -> RuntimeError {
+    RuntimeError::new(ErrorKind::Type, message)
+}
+
+pub fn arithmetic_error(message: impl Into<String>) -> RuntimeError {
+    RuntimeError::new(ErrorKind::Arithmetic, message)
+}
+
+pub fn index_error(message: impl Into<String>) -> RuntimeError {
+    RuntimeError::new(ErrorKind::Index, message)
+}
+
+pub fn key_error(message: impl Into<String>) -> RuntimeError {
+    RuntimeError::new(ErrorKind::Key, message)
+}
+
+pub fn name_error(message: impl Into<String>) -> RuntimeError {
+    RuntimeError::new(ErrorKind::Name, message)
+}
+
+pub fn value_error(message: impl Into<String>) -> RuntimeError {
+    RuntimeError::new(ErrorKind::Value, message)
+}
+
+// An error as scripts see it, once raised: the value bound by `catch`.
+#[derive(Debug)]
+pub struct ErrorObject {
+    pub kind: ErrorKind,
+    pub message: String,
+    // Where the error was raised, innermost first.
+    pub trace: Vec<String>,
+}
+
+impl Display for ErrorObject {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind.name(), self.message)
+    }
+}
@@ -0,0 +1,98 @@
+use crate::value::*;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+// `start..end` or `start..=end`, counting by `step`. A range only stores
+// its bounds, so `0..1000000` costs no more than `0..1`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Range {
+    pub start: i64,
+    pub end: i64,
+    pub step: i64,
+    pub inclusive: bool,
+}
+
+impl Range {
+    // Whether `n` lies on the range's side of `end`.
+    fn before_end(&self, n: i64) -> bool {
+        match (self.step > 0, self.inclusive) {
+            (true, true) => n <= self.end,
+            (true, false) => n < self.end,
+            (false, true) => n >= self.end,
+            (false, false) => n > self.end,
+        }
+    }
+
+    // The number of values the range visits.
+    pub fn count(&self) -> i128 {
+        let (start, end, step) = (self.start as i128, self.end as i128, self.step as i128);
+        let span = if self.step > 0 { end - start } else { start - end };
+        let step = step.abs();
+        match self.inclusive {
+            true if span >= 0 => span / step + 1,
+            false if span > 0 => (span + step - 1) / step,
+            _ => 0,
+        }
+    }
+
+    pub fn contains(&self, n: i64) -> bool {
+        let past_start = if self.step > 0 {
+            n >= self.start
+        } else {
+            n <= self.start
+        };
+        past_start && self.before_end(n) && (n as i128 - self.start as i128) % self.step as i128 == 0
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let dots = if self.inclusive { "..=" } else { ".." };
+        match self.step {
+            1 => write!(f, "{}{}{}", self.start, dots, self.end),
+            step => write!(f, "({}{}{}).step({})", self.start, dots, self.end, step),
+        }
+    }
+}
+
+// The state of a `for` loop over a value. Lists and maps are read live, so
+// elements added during the loop are visited and the loop stops early if
+// elements are removed.
+#[derive(Debug)]
+pub enum Iter {
+    List(Rc<ListCell>, usize),
+    // Visits the keys.
+    Map(Rc<MapCell>, usize),
+    Str(Vec<char>, usize),
+    // The next value, or None once stepping would overflow.
+    Range(Range, Option<i64>),
+}
+
+impl Iterator for Iter {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            Iter::List(list, index) => {
+                let item = list.borrow().get(*index).cloned()?;
+                *index += 1;
+                Some(item)
+            }
+            Iter::Map(map, index) => {
+                let key = map.borrow().entries().get(*index)?.0.clone();
+                *index += 1;
+                Some(key)
+            }
+            Iter::Str(chars, index) => {
+                let c = *chars.get(*index)?;
+                *index += 1;
+                Some(Value::Str(c.to_string()))
+            }
+            Iter::Range(range, next) => {
+                let n = next.filter(|&n| range.before_end(n))?;
+                *next = n.checked_add(range.step);
+                Some(Value::Int(n))
+            }
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use crate::chunk::*;
+use crate::value::*;
+
+// Machine-readable counterpart to `Chunk::disassemble`. Each instruction is
+// written on its own line so listings diff cleanly.
+impl Chunk {
+    pub fn disassemble_json(&self, name: &str) -> String {
+        let mut out = String::new();
+        out.push_str("{\n");
+        out.push_str(&format!("  \"name\": {},\n", string(name)));
+
+        out.push_str("  \"constants\": [");
+        for (index, value) in self.constants.values.iter().enumerate() {
+            out.push_str(if index == 0 { "\n    " } else { ",\n    " });
+            out.push_str(&value_json(value));
+        }
+        out.push_str(if self.constants.values.is_empty() {
+            "],\n"
+        } else {
+            "\n  ],\n"
+        });
+
+        out.push_str("  \"instructions\": [");
+        for (index, decoded) in self.instructions().enumerate() {
+            out.push_str(if index == 0 { "\n    " } else { ",\n    " });
+            out.push_str(&match decoded {
+                Ok(instruction) => instruction_json(&instruction),
+                Err(DecodeError::UnknownOpcode { offset, byte }) => format!(
+                    "{{\"offset\": {}, \"error\": {}}}",
+                    offset,
+                    string(&format!("unknown opcode {}", byte))
+                ),
+                Err(DecodeError::TruncatedOperand { offset, opcode }) => format!(
+                    "{{\"offset\": {}, \"error\": {}}}",
+                    offset,
+                    string(&format!("{} is missing its operand", opcode.name()))
+                ),
+            });
+        }
+        out.push_str(if self.code.is_empty() {
+            "],\n"
+        } else {
+            "\n  ],\n"
+        });
+
+        out.push_str("  \"handlers\": [");
+        for (index, handler) in self.handlers.iter().enumerate() {
+            out.push_str(if index == 0 { "\n    " } else { ",\n    " });
+            out.push_str(&format!(
+                "{{\"start\": {}, \"end\": {}, \"target\": {}, \"depth\": {}}}",
+                handler.start, handler.end, handler.target, handler.depth
+            ));
+        }
+        out.push_str(if self.handlers.is_empty() {
+            "]\n"
+        } else {
+            "\n  ]\n"
+        });
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn instruction_json(instruction: &Instruction) -> String {
+    // Jumps are written as their target offset, matching the text listing.
+    let operands: Vec<String> = instruction
+        .operands
+        .iter()
+        .zip(instruction.opcode.info().operands)
+        .map(|(&operand, kind)| match kind {
+            Operand::Jump | Operand::Loop => instruction.jump_target().unwrap_or(0),
+            _ => operand,
+        })
+        .map(|operand| operand.to_string())
+        .collect();
+    let constant = match &instruction.resolved_constant {
+        Some(value) => value_json(value),
+        None => "null".to_string(),
+    };
+    format!(
+        "{{\"offset\": {}, \"opcode\": {}, \"operands\": [{}], \"line\": {}, \"constant\": {}}}",
+        instruction.offset,
+        string(instruction.opcode.name()),
+        operands.join(", "),
+        instruction.line,
+        constant
+    )
+}
+
+fn value_json(value: &Value) -> String {
+    match value {
+        Value::Nil => "{\"type\": \"nil\"}".to_string(),
+        Value::Boolean(b) => format!("{{\"type\": \"bool\", \"value\": {}}}", b),
+        // JSON has no NaN or infinities, so those are written as strings.
+        Value::Number(n) if n.is_finite() => format!("{{\"type\": \"number\", \"value\": {}}}", n),
+        Value::Number(n) => format!(
+            "{{\"type\": \"number\", \"value\": {}}}",
+            string(&n.to_string())
+        ),
+        Value::Int(n) => format!("{{\"type\": \"int\", \"value\": {}}}", n),
+        // Big integers would lose precision in most JSON readers, so their
+        // digits are written as a string.
+        Value::BigInt(n) => format!(
+            "{{\"type\": \"bigint\", \"value\": {}}}",
+            string(&n.to_string())
+        ),
+        Value::Str(s) => format!("{{\"type\": \"string\", \"value\": {}}}", string(s)),
+        Value::List(list) => {
+            let items: Vec<String> = list.borrow().iter().map(value_json).collect();
+            format!("{{\"type\": \"list\", \"value\": [{}]}}", items.join(", "))
+        }
+        Value::Map(map) => {
+            let entries: Vec<String> = map
+                .borrow()
+                .entries()
+                .iter()
+                .map(|(key, value)| format!("[{}, {}]", value_json(key), value_json(value)))
+                .collect();
+            format!("{{\"type\": \"map\", \"value\": [{}]}}", entries.join(", "))
+        }
+        Value::Range(range) => format!(
+            "{{\"type\": \"range\", \"start\": {}, \"end\": {}, \"step\": {}, \"inclusive\": {}}}",
+            range.start, range.end, range.step, range.inclusive
+        ),
+        Value::Iterator(_) => "{\"type\": \"iterator\"}".to_string(),
+        Value::Error(error) => format!(
+            "{{\"type\": \"error\", \"kind\": {}, \"message\": {}}}",
+            string(error.kind.name()),
+            string(&error.message)
+        ),
+    }
+}
+
+fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn listing(source: &str) -> String {
+        let mut chunk = Chunk::new();
+        assert!(!Compiler::new(&mut chunk).compile(source.to_string()));
+        chunk.disassemble_json("test")
+    }
+
+    #[test]
+    fn writes_big_integers_as_strings() {
+        let json = listing("print 123456789012345678901234567890;");
+        assert!(
+            json.contains("{\"type\": \"bigint\", \"value\": \"123456789012345678901234567890\"}")
+        );
+    }
+
+    #[test]
+    fn writes_jump_targets_like_the_text_listing() {
+        let mut chunk = Chunk::new();
+        assert!(!Compiler::new(&mut chunk).compile("for i in 0..3 { print i; }".to_string()));
+        let json = chunk.disassemble_json("test");
+        let text = chunk.listing("test");
+        let mut jumps = 0;
+        for instruction in chunk.instructions().map(Result::unwrap) {
+            let Some(target) = instruction.jump_target() else {
+                continue;
+            };
+            jumps += 1;
+            let name = instruction.opcode.name();
+            assert!(json.contains(&format!(
+                "{{\"offset\": {}, \"opcode\": \"{}\", \"operands\": [{}],",
+                instruction.offset, name, target
+            )));
+            assert!(text.contains(&format!("{:<16} {:4}", name, target)));
+        }
+        assert_eq!(jumps, 2);
+    }
+}
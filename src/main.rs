@@ -1,10 +1,22 @@
+mod assembler;
+mod bigint;
+mod builtins;
 mod chunk;
 mod compiler;
+mod error;
+mod iter;
+mod json;
+mod map;
 mod scanner;
+mod serialize;
 mod value;
 mod vm;
 mod token;
+mod verifier;
+mod xid;
 
+use chunk::Chunk;
+use compiler::Compiler;
 use vm::*;
 
 fn repl(vm: &mut vm::VM) {
@@ -29,25 +41,118 @@ fn repl(vm: &mut vm::VM) {
     }
 }
 
-fn eval(source: &str) {
-    let mut vm = vm::VM::new();
-    match vm.interpret(source.to_string()) {
+fn exit_with(result: InterpretResult) {
+    match result {
         InterpretResult::CompileError => std::process::exit(65),
         InterpretResult::RuntimeError => std::process::exit(70),
         _ => (),
     }
 }
+
+fn read_file(path: &str) -> Vec<u8> {
+    match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("Could not read file \"{}\": {}", path, err);
+            std::process::exit(74);
+        }
+    }
+}
+
+fn read_source(path: &str, bytes: Vec<u8>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(source) => source,
+        Err(err) => {
+            let line = 1 + err.as_bytes()[..err.utf8_error().valid_up_to()]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count();
+            eprintln!("[line {}] Error: \"{}\" is not valid UTF-8.", line, path);
+            std::process::exit(65);
+        }
+    }
+}
+
+fn eval(source: &str) {
+    let mut vm = vm::VM::new();
+    exit_with(vm.interpret(source.to_string()));
+}
+
+fn load_chunk(path: &str) -> Chunk {
+    let bytes = read_file(path);
+    if path.ends_with(".arcb") || path.ends_with(".arcasm") {
+        let chunk = if path.ends_with(".arcb") {
+            Chunk::deserialize(&bytes).map_err(|err| err.to_string())
+        } else {
+            assembler::assemble(&read_source(path, bytes)).map_err(|err| err.to_string())
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                eprintln!("Could not load \"{}\": {}", path, err);
+                std::process::exit(65);
+            }
+        };
+        if let Err(err) = verifier::verify(&chunk) {
+            eprintln!("Rejected \"{}\": {}", path, err);
+            std::process::exit(65);
+        }
+        return chunk;
+    }
+
+    let mut chunk = Chunk::new();
+    let mut compiler = Compiler::new(&mut chunk);
+    if compiler.compile(read_source(path, bytes)) {
+        std::process::exit(65);
+    }
+    chunk
+}
+
+fn run_bytecode(path: &str) {
+    let chunk = load_chunk(path);
+    let mut vm = vm::VM::new();
+    exit_with(vm.interpret_chunk(chunk));
+}
+
+fn compile_file(path: &str, out: &str) {
+    let chunk = load_chunk(path);
+    if let Err(err) = std::fs::write(out, chunk.serialize()) {
+        eprintln!("Could not write file \"{}\": {}", out, err);
+        std::process::exit(74);
+    }
+}
+
 fn main() {
     let mut vm = vm::VM::new();
-    match std::env::args().len() {
+    let args: Vec<String> = std::env::args().collect();
+    match args.len() {
         1 => repl(&mut vm),
         2 => {
-            let args: Vec<String> = std::env::args().collect();
             let filename = &args[1];
-            let source = std::fs::read_to_string(filename).unwrap();
-            let _ = eval(&source);
+            if filename.ends_with(".arcb") || filename.ends_with(".arcasm") {
+                run_bytecode(filename);
+            } else {
+                let source = read_source(filename, read_file(filename));
+                eval(&source);
+            }
+        }
+        3 | 4 if args[1] == "compile" => {
+            let out = match args.get(3) {
+                Some(out) => out.clone(),
+                None => std::path::Path::new(&args[2])
+                    .with_extension("arcb")
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            compile_file(&args[2], &out);
+        }
+        3 if args[1] == "disassemble" => load_chunk(&args[2]).disassemble(&args[2]),
+        4 if args[1] == "disassemble" && args[2] == "--json" => {
+            print!("{}", load_chunk(&args[3]).disassemble_json(&args[3]))
         }
-        _ => println!("Usage: arc [path]"),
+        _ => println!(
+            "Usage: arc [path]\n       arc compile <path> [output.arcb]\n       arc disassemble [--json] <path>"
+        ),
     }
     vm.free();
 }
@@ -0,0 +1,93 @@
+use crate::bigint::BigInt;
+use crate::error::*;
+use crate::iter::Range;
+use crate::value::*;
+use std::collections::HashMap;
+
+// A map key reduced to a hashable form. Keys that are equal as values
+// reduce to the same HashKey, so `1`, `1.0` and a whole-valued BigInt
+// float are one key. Lists and maps are mutable, so they cannot be keys.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Nil,
+    Boolean(bool),
+    Int(i64),
+    BigInt(BigInt),
+    // The bits of a float with a fractional part.
+    Float(u64),
+    Str(String),
+    Range(Range),
+}
+
+impl HashKey {
+    pub fn new(value: &Value) -> Result<Self, RuntimeError> {
+        match value {
+            Value::Nil => Ok(HashKey::Nil),
+            Value::Boolean(b) => Ok(HashKey::Boolean(*b)),
+            Value::Int(n) => Ok(HashKey::Int(*n)),
+            Value::BigInt(n) => Ok(HashKey::BigInt(n.clone())),
+            Value::Number(n) if n.is_nan() => Err(value_error("NaN cannot be a map key.")),
+            Value::Number(n) => match BigInt::from_f64(*n) {
+                Some(whole) => match whole.to_i64() {
+                    Some(n) => Ok(HashKey::Int(n)),
+                    None => Ok(HashKey::BigInt(whole)),
+                },
+                None => Ok(HashKey::Float(n.to_bits())),
+            },
+            Value::Str(s) => Ok(HashKey::Str(s.clone())),
+            Value::Range(range) => Ok(HashKey::Range(*range)),
+            Value::List(_) => Err(type_error("A list cannot be a map key.")),
+            Value::Map(_) => Err(type_error("A map cannot be a map key.")),
+            Value::Iterator(_) => Err(type_error("An iterator cannot be a map key.")),
+            Value::Error(_) => Err(type_error("An error cannot be a map key.")),
+        }
+    }
+}
+
+// Entries keep their insertion order. Overwriting a key keeps its place;
+// removing one shifts the later entries down.
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    indices: HashMap<HashKey, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[(Value, Value)] {
+        &self.entries
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, RuntimeError> {
+        let key = HashKey::new(key)?;
+        Ok(self.indices.get(&key).map(|&index| &self.entries[index].1))
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), RuntimeError> {
+        let hash_key = HashKey::new(&key)?;
+        match self.indices.get(&hash_key) {
+            Some(&index) => self.entries[index].1 = value,
+            None => {
+                self.indices.insert(hash_key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, RuntimeError> {
+        let Some(index) = self.indices.remove(&HashKey::new(key)?) else {
+            return Ok(None);
+        };
+        let (_, value) = self.entries.remove(index);
+        for later in self.indices.values_mut() {
+            if *later > index {
+                *later -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+}
@@ -1,24 +1,54 @@
 use crate::token::*;
+use crate::xid::*;
 
+// Works on the UTF-8 bytes of the source. All positions are byte offsets,
+// and every delimiter the scanner looks for is ASCII, so token boundaries
+// always fall on character boundaries.
 pub struct Scanner {
     pub source: String,
     start: usize,
     current: usize,
     pub line: usize,
+    // Unclosed `{` count for each `${` we are inside, innermost last.
+    interpolations: Vec<usize>,
+    // The type of the last token returned, and the line it ended on.
+    previous: TokenType,
+    previous_line: usize,
+    // Set after the `}` of an empty `${}`, which is returned as a token of
+    // its own: the string resumes with the next token.
+    resume_string: bool,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
         Self {
-            source: source + "\0",
+            source,
             start: 0,
             current: 0,
             line: 1,
+            interpolations: Vec::new(),
+            previous: TokenType::EOF,
+            previous_line: 1,
+            resume_string: false,
         }
     }
 
     pub fn scan_token(&mut self) -> Token {
-        self.skip_whitespace();
+        let token = self.next_token();
+        self.previous = token.token_type;
+        self.previous_line = self.line;
+        token
+    }
+
+    fn next_token(&mut self) -> Token {
+        if self.resume_string {
+            self.resume_string = false;
+            return self.string_body(1, 0, false);
+        }
+
+        if let Some(error) = self.skip_whitespace() {
+            return error;
+        }
 
         self.start = self.current;
 
@@ -28,184 +58,451 @@ impl Scanner {
 
         let c = self.advance();
         match c {
-            '(' => return self.make_token(TokenType::LeftParen),
-            ')' => return self.make_token(TokenType::RightParen),
-            '{' => return self.make_token(TokenType::LeftBrace),
-            '}' => return self.make_token(TokenType::RightBrace),
-            ';' => return self.make_token(TokenType::Semicolon),
-            ',' => return self.make_token(TokenType::Comma),
-            '.' => return self.make_token(TokenType::Dot),
-            '-' => return self.make_token(TokenType::Minus),
-            '+' => return self.make_token(TokenType::Plus),
-            '/' => return self.make_token(TokenType::Slash),
-            '*' => return self.make_token(TokenType::Star),
-
-            '!' => {
-                if self.match_char('=') {
-                    return self.make_token(TokenType::BangEqual);
+            b'(' => self.make_token(TokenType::LeftParen),
+            b')' => self.make_token(TokenType::RightParen),
+            b'{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.make_token(TokenType::LeftBrace)
+            }
+            b'}' => {
+                // The brace closing a `${`: resume the enclosing string.
+                if self.interpolations.last() == Some(&0) {
+                    self.interpolations.pop();
+                    // `${}` has no expression. The `}` is passed on for the
+                    // parser to report.
+                    if self.previous == TokenType::Interpolation {
+                        self.resume_string = true;
+                        return self.make_token(TokenType::RightBrace);
+                    }
+                    return self.string_body(1, 0, false);
+                }
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth -= 1;
+                }
+                self.make_token(TokenType::RightBrace)
+            }
+            b'[' => self.make_token(TokenType::LeftBracket),
+            b']' => self.make_token(TokenType::RightBracket),
+            b';' => self.make_token(TokenType::Semicolon),
+            b':' => self.make_token(TokenType::Colon),
+            b'?' => {
+                if self.match_char(b'?') {
+                    self.make_token(TokenType::QuestionQuestion)
+                } else if self.match_char(b'.') {
+                    self.make_token(TokenType::QuestionDot)
                 } else {
-                    return self.make_token(TokenType::Bang);
+                    self.make_token(TokenType::Question)
                 }
             }
-
-            '=' => {
-                if self.match_char('=') {
-                    return self.make_token(TokenType::EqualEqual);
+            b',' => self.make_token(TokenType::Comma),
+            b'.' => {
+                if self.match_char(b'.') {
+                    self.with_equal(TokenType::DotDot, TokenType::DotDotEqual)
+                } else {
+                    self.make_token(TokenType::Dot)
+                }
+            }
+            b'-' => {
+                if self.match_char(b'-') {
+                    self.make_token(TokenType::MinusMinus)
+                } else {
+                    self.with_equal(TokenType::Minus, TokenType::MinusEqual)
+                }
+            }
+            b'+' => {
+                if self.match_char(b'+') {
+                    self.make_token(TokenType::PlusPlus)
+                } else {
+                    self.with_equal(TokenType::Plus, TokenType::PlusEqual)
+                }
+            }
+            b'/' if self.peek() == b'/' && !self.is_doc_comment_at(self.start) => {
+                self.advance();
+                self.with_equal(TokenType::SlashSlash, TokenType::SlashSlashEqual)
+            }
+            b'/' if self.peek() == b'/' => {
+                // skip_whitespace stops only at `///` doc comments and at
+                // floor division.
+                while self.peek() != b'\n' && !self.is_at_end() {
+                    self.advance();
+                }
+                let text = self.source[self.start + 3..self.current].to_string();
+                self.make_string(text, TokenType::DocComment)
+            }
+            b'/' => self.with_equal(TokenType::Slash, TokenType::SlashEqual),
+            b'*' => {
+                if self.match_char(b'*') {
+                    self.with_equal(TokenType::StarStar, TokenType::StarStarEqual)
                 } else {
-                    return self.make_token(TokenType::Equal);
+                    self.with_equal(TokenType::Star, TokenType::StarEqual)
                 }
             }
+            b'%' => self.with_equal(TokenType::Percent, TokenType::PercentEqual),
+            b'~' => self.make_token(TokenType::Tilde),
+            b'&' => self.with_equal(TokenType::Ampersand, TokenType::AmpersandEqual),
+            b'|' => self.with_equal(TokenType::Pipe, TokenType::PipeEqual),
+            b'^' => self.with_equal(TokenType::Caret, TokenType::CaretEqual),
 
-            '<' => {
-                if self.match_char('=') {
-                    return self.make_token(TokenType::LessEqual);
+            b'!' => {
+                if self.match_char(b'=') {
+                    self.make_token(TokenType::BangEqual)
                 } else {
-                    return self.make_token(TokenType::Less);
+                    self.make_token(TokenType::Bang)
                 }
             }
 
-            '>' => {
-                if self.match_char('=') {
-                    return self.make_token(TokenType::GreaterEqual);
+            b'=' => {
+                if self.match_char(b'=') {
+                    self.make_token(TokenType::EqualEqual)
+                } else if self.match_char(b'>') {
+                    self.make_token(TokenType::EqualGreater)
                 } else {
-                    return self.make_token(TokenType::Greater);
+                    self.make_token(TokenType::Equal)
                 }
             }
 
-            '"' => {
-                while self.peek() != '"' && !self.is_at_end() {
-                    if self.peek() == '\n' {
-                        self.line += 1;
-                    }
-                    self.advance();
+            b'<' => {
+                if self.match_char(b'=') {
+                    self.make_token(TokenType::LessEqual)
+                } else if self.match_char(b'<') {
+                    self.with_equal(TokenType::LessLess, TokenType::LessLessEqual)
+                } else {
+                    self.make_token(TokenType::Less)
                 }
+            }
 
-                if self.is_at_end() {
-                    return self.make_error("Unterminated string.");
+            b'>' => {
+                if self.match_char(b'=') {
+                    self.make_token(TokenType::GreaterEqual)
+                } else if self.match_char(b'>') {
+                    self.with_equal(TokenType::GreaterGreater, TokenType::GreaterGreaterEqual)
+                } else {
+                    self.make_token(TokenType::Greater)
                 }
+            }
 
-                self.advance();
-                return self.make_token(TokenType::String);
+            b'"' => self.string(0, false),
+
+            b'0'..=b'9' => self.number(),
+
+            b'r' if self.raw_string_hashes().is_some() => {
+                let hashes = self.raw_string_hashes().unwrap();
+                self.current += hashes + 1;
+                self.string(hashes, true)
             }
 
-            '0'..='9' => {
-                while self.peek().is_digit(10) {
-                    self.advance();
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.identifier(),
 
-                    if self.peek() == '.' && self.peek_next().is_digit(10) {
-                        self.advance();
+            _ => {
+                // Re-read the whole character, which may span several bytes.
+                self.current = self.start;
+                let c = self.peek_char().unwrap();
+                self.current += c.len_utf8();
 
-                        while self.peek().is_digit(10) {
-                            self.advance();
-                        }
-                    }
+                if is_identifier_start(c) {
+                    self.identifier()
+                } else {
+                    self.make_error("Unexpected character.")
                 }
+            }
+        }
+    }
+
+    // An operator that may be followed by `=` to make it a compound
+    // assignment.
+    fn with_equal(&mut self, plain: TokenType, assign: TokenType) -> Token {
+        if self.match_char(b'=') {
+            self.make_token(assign)
+        } else {
+            self.make_token(plain)
+        }
+    }
+
+    // After an `r`, the number of `#`s before the opening quote of a raw
+    // string, or None if this is not a raw string.
+    fn raw_string_hashes(&self) -> Option<usize> {
+        let rest = &self.source.as_bytes()[self.current..];
+        let hashes = rest.iter().take_while(|&&b| b == b'#').count();
+        (rest.get(hashes) == Some(&b'"')).then_some(hashes)
+    }
+
+    // Scans a string whose opening quote has been consumed: "..." or a
+    // triple-quoted block, optionally raw (r"...", r#"..."#). The token's
+    // lexeme is the string's value, with escapes processed and block
+    // indentation removed.
+    fn string(&mut self, hashes: usize, raw: bool) -> Token {
+        let triple = self.peek() == b'"' && self.peek_next() == b'"';
+        if triple {
+            self.current += 2;
+        }
+        self.string_body(if triple { 3 } else { 1 }, hashes, raw)
+    }
 
-                return self.make_token(TokenType::Number);
+    // Plain "..." strings may contain `${expression}`. The text before each
+    // `${` becomes an Interpolation token, the expression is scanned as
+    // ordinary tokens, and the matching `}` resumes the string here.
+    fn string_body(&mut self, quotes: usize, hashes: usize, raw: bool) -> Token {
+        let interpolates = quotes == 1 && !raw;
+        let start_line = self.line;
+        let body_start = self.current;
+        loop {
+            if self.is_at_end() {
+                return self.make_error("Unterminated string.");
+            }
+            if self.closes_string(quotes, hashes) {
+                break;
+            }
+            if interpolates && self.peek() == b'$' && self.peek_next() == b'{' {
+                let body = self.source[body_start..self.current].to_string();
+                self.current += 2;
+                self.interpolations.push(0);
+                return self.cooked_string(body, TokenType::Interpolation, start_line);
+            }
+            let mut c = self.advance();
+            if c == b'\\' && !raw && !self.is_at_end() {
+                c = self.advance();
+            }
+            if c == b'\n' {
+                self.line += 1;
             }
+        }
+        let body = &self.source[body_start..self.current];
+        self.current += quotes + hashes;
 
-            'a'..='z' | 'A'..='Z' | '_' => {
-                while self.peek().is_alphanumeric() {
-                    self.advance();
-                }
+        let body = if quotes == 3 { dedent(body) } else { body.to_string() };
+        if raw {
+            return self.make_string(body, TokenType::String);
+        }
+        self.cooked_string(body, TokenType::String, start_line)
+    }
+
+    fn cooked_string(&self, body: String, token_type: TokenType, start_line: usize) -> Token {
+        match unescape(&body) {
+            Ok(value) => self.make_string(value, token_type),
+            Err((offset, message)) => Token {
+                token_type: TokenType::Error,
+                lexeme: message,
+                line: start_line + body[..offset].matches('\n').count(),
+            },
+        }
+    }
+
+    fn closes_string(&self, quotes: usize, hashes: usize) -> bool {
+        let rest = &self.source.as_bytes()[self.current..];
+        rest.len() >= quotes + hashes
+            && rest[..quotes].iter().all(|&b| b == b'"')
+            && rest[quotes..quotes + hashes].iter().all(|&b| b == b'#')
+    }
+
+    fn make_string(&self, value: String, token_type: TokenType) -> Token {
+        Token {
+            token_type,
+            lexeme: value,
+            line: self.line,
+        }
+    }
+
+    // Takes everything that could belong to the literal, then checks its
+    // shape, so `0x1G` or `1e` is one error rather than a number followed
+    // by stray tokens.
+    fn number(&mut self) -> Token {
+        let radix = self.start + 1 == self.current
+            && self.source.as_bytes()[self.start] == b'0'
+            && matches!(self.peek(), b'x' | b'X' | b'b' | b'B' | b'o' | b'O');
+
+        loop {
+            let c = self.peek();
+            let previous = self.source.as_bytes()[self.current - 1];
+            let fraction = c == b'.' && self.peek_next().is_ascii_digit();
+            let exponent_sign = matches!(c, b'+' | b'-')
+                && matches!(previous, b'e' | b'E')
+                && self.peek_next().is_ascii_digit();
+            if !(c.is_ascii_alphanumeric() || c == b'_' || !radix && (fraction || exponent_sign)) {
+                break;
+            }
+            self.advance();
+        }
 
-                return self.make_token(self.identifier_type());
+        match check_number(&self.source[self.start..self.current]) {
+            Ok(()) => self.make_token(TokenType::Number),
+            Err(message) => self.make_error(&message),
+        }
+    }
+
+    fn identifier(&mut self) -> Token {
+        while let Some(c) = self.peek_char() {
+            if !is_identifier_continue(c) {
+                break;
             }
-            _ => return self.make_error("Unexpected character."),
+            self.current += c.len_utf8();
         }
+
+        self.make_token(self.identifier_type())
     }
 
-    pub fn advance(&mut self) -> char {
-        let c = self.source.chars().nth(self.current).unwrap();
+    fn peek_char(&self) -> Option<char> {
+        self.source[self.current..].chars().next()
+    }
+
+    pub fn advance(&mut self) -> u8 {
+        let c = self.source.as_bytes()[self.current];
         self.current += 1;
         c
     }
 
     fn is_at_end(&self) -> bool {
-        self.source.chars().nth(self.current) == Some('\0')
+        self.current >= self.source.len()
     }
 
-    fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() || self.source.chars().nth(self.current) != Some(expected) {
+    fn match_char(&mut self, expected: u8) -> bool {
+        if self.is_at_end() || self.peek() != expected {
             return false;
         }
         self.current += 1;
         true
     }
 
-    fn skip_whitespace(&mut self) {
+    // Skips whitespace and comments, stopping at a `///` doc comment so it
+    // can be returned as a token. An unterminated block comment is an error.
+    fn skip_whitespace(&mut self) -> Option<Token> {
         loop {
             let c = self.peek();
             match c {
-                ' ' | '\r' | '\t' => {
+                b' ' | b'\r' | b'\t' => {
                     self.advance();
                 }
-                '\n' => {
+                b'\n' => {
                     self.line += 1;
                     self.advance();
                 }
-                '/' => {
-                    if self.peek_next() == '/' {
-                        while self.peek() != '\n' && !self.is_at_end() {
-                            self.advance();
-                        }
-                    } else {
-                        return;
+                b'/' if self.peek_next() == b'/' => {
+                    if self.is_doc_comment_at(self.current) || self.is_floor_division() {
+                        return None;
+                    }
+                    while self.peek() != b'\n' && !self.is_at_end() {
+                        self.advance();
+                    }
+                }
+                b'/' if self.peek_next() == b'*' => {
+                    if !self.block_comment() {
+                        return Some(self.make_error("Unterminated comment."));
                     }
                 }
-                _ => return,
+                _ => return None,
             }
         }
     }
 
-    fn peek_next(&self) -> char {
-        if self.current + 1 >= self.source.len() {
-            return '\0';
-        }
-        self.source.chars().nth(self.current + 1).unwrap()
+    // `///` starts a doc comment, but `////` is an ordinary comment.
+    fn is_doc_comment_at(&self, offset: usize) -> bool {
+        let rest = &self.source.as_bytes()[offset..];
+        rest.starts_with(b"///") && !rest.starts_with(b"////")
     }
 
-    fn peek(&self) -> char {
-        self.source.chars().nth(self.current).unwrap()
+    // Whether the `//` at the current position is floor division rather
+    // than a comment: it must directly follow an operand on the same line,
+    // as in `total // 2`. After an operator or a `;`, or at the start of a
+    // line, `//` is a comment, and `///` or `////` always are.
+    fn is_floor_division(&self) -> bool {
+        let ends_operand = matches!(
+            self.previous,
+            TokenType::Identifier
+                | TokenType::Number
+                | TokenType::String
+                | TokenType::True
+                | TokenType::False
+                | TokenType::Nil
+                | TokenType::This
+                | TokenType::RightParen
+                | TokenType::RightBracket
+        );
+        ends_operand
+            && self.line == self.previous_line
+            && self.source.as_bytes().get(self.current + 2) != Some(&b'/')
     }
 
-    fn identifier_type(&self) -> TokenType {
-        match self.source.chars().nth(self.start).unwrap() {
-            'a' => return self.check_keyword(1, 2, "nd", TokenType::And),
-            'c' => return self.check_keyword(1, 4, "lass", TokenType::Class),
-            'e' => return self.check_keyword(1, 3, "lse", TokenType::Else),
-            'f' => {
-                if self.current - self.start > 1 {
-                    match self.source.chars().nth(self.start + 1).unwrap() {
-                        'a' => return self.check_keyword(2, 3, "lse", TokenType::False),
-                        'n' => return self.check_keyword(2, 1, "n", TokenType::Fn),
-                        'o' => return self.check_keyword(2, 1, "r", TokenType::For),
-                        _ => return TokenType::Identifier,
+    // Skips a `/* ... */` comment, which may contain nested block comments.
+    // Returns false if the input ends before the comment is closed.
+    fn block_comment(&mut self) -> bool {
+        let mut depth = 0;
+        while !self.is_at_end() {
+            match (self.peek(), self.peek_next()) {
+                (b'/', b'*') => {
+                    self.current += 2;
+                    depth += 1;
+                }
+                (b'*', b'/') => {
+                    self.current += 2;
+                    depth -= 1;
+                    if depth == 0 {
+                        return true;
                     }
-                } else {
-                    return TokenType::Identifier;
-                }
-            }
-            'i' => return self.check_keyword(1, 1, "f", TokenType::If),
-            'n' => return self.check_keyword(1, 2, "il", TokenType::Nil),
-            'o' => return self.check_keyword(1, 1, "r", TokenType::Or),
-            'p' => return self.check_keyword(1, 4, "rint", TokenType::Print),
-            'r' => return self.check_keyword(1, 5, "eturn", TokenType::Return),
-            's' => return self.check_keyword(1, 4, "uper", TokenType::Super),
-            't' => {
-                if self.current - self.start > 1 {
-                    match self.source.chars().nth(self.start + 1).unwrap() {
-                        'h' => return self.check_keyword(2, 2, "is", TokenType::This),
-                        'r' => return self.check_keyword(2, 2, "ue", TokenType::True),
-                        _ => return TokenType::Identifier,
+                }
+                (c, _) => {
+                    if c == b'\n' {
+                        self.line += 1;
                     }
-                } else {
-                    return TokenType::Identifier;
+                    self.advance();
                 }
             }
-            'v' => return self.check_keyword(1, 2, "ar", TokenType::Var),
-            'w' => return self.check_keyword(1, 4, "hile", TokenType::While),
-            _ => return TokenType::Identifier,
+        }
+        false
+    }
+
+    fn peek_next(&self) -> u8 {
+        self.source.as_bytes().get(self.current + 1).copied().unwrap_or(b'\0')
+    }
+
+    fn peek(&self) -> u8 {
+        self.source.as_bytes().get(self.current).copied().unwrap_or(b'\0')
+    }
+
+    fn identifier_type(&self) -> TokenType {
+        let lexeme = &self.source.as_bytes()[self.start..self.current];
+        match lexeme[0] {
+            b'a' => self.check_keyword(1, 2, "nd", TokenType::And),
+            b'b' => self.check_keyword(1, 4, "reak", TokenType::Break),
+            b'c' if lexeme.len() > 1 => match lexeme[1] {
+                b'a' => self.check_keyword(2, 3, "tch", TokenType::Catch),
+                b'l' => self.check_keyword(2, 3, "ass", TokenType::Class),
+                b'o' if lexeme.len() > 3 => match lexeme[3] {
+                    b's' => self.check_keyword(2, 3, "nst", TokenType::Const),
+                    b't' => self.check_keyword(2, 6, "ntinue", TokenType::Continue),
+                    _ => TokenType::Identifier,
+                },
+                _ => TokenType::Identifier,
+            },
+            b'e' => self.check_keyword(1, 3, "lse", TokenType::Else),
+            b'f' if lexeme.len() > 1 => match lexeme[1] {
+                b'a' => self.check_keyword(2, 3, "lse", TokenType::False),
+                b'i' => self.check_keyword(2, 5, "nally", TokenType::Finally),
+                b'n' => self.check_keyword(2, 0, "", TokenType::Fn),
+                b'o' => self.check_keyword(2, 1, "r", TokenType::For),
+                _ => TokenType::Identifier,
+            },
+            b'i' if lexeme.len() > 1 => match lexeme[1] {
+                b'f' => self.check_keyword(2, 0, "", TokenType::If),
+                b'n' => self.check_keyword(2, 0, "", TokenType::In),
+                _ => TokenType::Identifier,
+            },
+            b'm' => self.check_keyword(1, 4, "atch", TokenType::Match),
+            b'n' => self.check_keyword(1, 2, "il", TokenType::Nil),
+            b'o' => self.check_keyword(1, 1, "r", TokenType::Or),
+            b'p' => self.check_keyword(1, 4, "rint", TokenType::Print),
+            b'r' => self.check_keyword(1, 5, "eturn", TokenType::Return),
+            b's' => self.check_keyword(1, 4, "uper", TokenType::Super),
+            b't' if lexeme.len() > 2 => match (lexeme[1], lexeme[2]) {
+                (b'h', b'i') => self.check_keyword(3, 1, "s", TokenType::This),
+                (b'h', b'r') => self.check_keyword(3, 2, "ow", TokenType::Throw),
+                (b'r', b'u') => self.check_keyword(3, 1, "e", TokenType::True),
+                (b'r', b'y') => self.check_keyword(3, 0, "", TokenType::Try),
+                _ => TokenType::Identifier,
+            },
+            b'v' => self.check_keyword(1, 2, "ar", TokenType::Var),
+            b'w' => self.check_keyword(1, 4, "hile", TokenType::While),
+            _ => TokenType::Identifier,
         }
     }
 
@@ -216,9 +513,9 @@ impl Scanner {
         rest: &str,
         token_type: TokenType,
     ) -> TokenType {
-        
+        let bytes = self.source.as_bytes();
         if self.current - self.start == start + length
-            && self.source[self.start + start..self.start + start + length].eq(rest)
+            && &bytes[self.start + start..self.start + start + length] == rest.as_bytes()
         {
             return token_type;
         }
@@ -227,7 +524,7 @@ impl Scanner {
 
     fn make_token(&self, token_type: TokenType) -> Token {
         Token {
-            token_type: token_type,
+            token_type,
             lexeme: self.source[self.start..self.current].to_string(),
             line: self.line,
         }
@@ -241,3 +538,683 @@ impl Scanner {
         }
     }
 }
+
+// Number literals are decimal, with an optional fraction and exponent
+// (`12`, `1.5`, `2.5e-3`), or integers in hex, binary or octal (`0xFF`,
+// `0b1010`, `0o17`). Any run of digits may use `_` between digits.
+fn check_number(text: &str) -> Result<(), String> {
+    let prefixed = |prefix: &str| {
+        text.get(..2)
+            .filter(|p| p.eq_ignore_ascii_case(prefix))
+            .map(|_| &text[2..])
+    };
+    let (digits, radix, kind) = if let Some(digits) = prefixed("0x") {
+        (digits, 16, "hex")
+    } else if let Some(digits) = prefixed("0b") {
+        (digits, 2, "binary")
+    } else if let Some(digits) = prefixed("0o") {
+        (digits, 8, "octal")
+    } else {
+        return check_decimal(text);
+    };
+
+    if let Some(c) = digits.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+        return Err(format!("Invalid digit '{}' in {} literal.", c, kind));
+    }
+    check_digits(digits, kind)
+}
+
+fn check_decimal(text: &str) -> Result<(), String> {
+    if let Some(c) = text
+        .chars()
+        .find(|c| !c.is_ascii_digit() && !matches!(c, '_' | '.' | 'e' | 'E' | '+' | '-'))
+    {
+        return Err(format!("Invalid character '{}' in number literal.", c));
+    }
+
+    let (mantissa, exponent) = match text.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (text, None),
+    };
+    let (integer, fraction) = match mantissa.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (mantissa, None),
+    };
+
+    check_digits(integer, "number")?;
+    if let Some(fraction) = fraction {
+        if fraction.contains('.') {
+            return Err("Number literal has more than one decimal point.".to_string());
+        }
+        check_digits(fraction, "number")?;
+    }
+    if let Some(exponent) = exponent {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if exponent.is_empty() {
+            return Err("Expect digits after the exponent in number literal.".to_string());
+        }
+        if exponent.contains(['e', 'E']) {
+            return Err("Number literal has more than one exponent.".to_string());
+        }
+        if exponent.contains('.') {
+            return Err("Exponent in number literal must be an integer.".to_string());
+        }
+        check_digits(exponent, "number")?;
+    }
+    Ok(())
+}
+
+fn check_digits(digits: &str, kind: &str) -> Result<(), String> {
+    if digits.is_empty() {
+        return Err(format!("Expect digits in {} literal.", kind));
+    }
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(format!("'_' in a {} literal must sit between digits.", kind));
+    }
+    Ok(())
+}
+
+// Processes the escapes \n \t \r \0 \\ \" \' \$ and \u{XXXX}. On failure,
+// returns the byte offset of the bad escape along with the message.
+fn unescape(body: &str) -> Result<String, (usize, String)> {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.char_indices();
+
+    while let Some((offset, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some((_, 'n')) => '\n',
+            Some((_, 't')) => '\t',
+            Some((_, 'r')) => '\r',
+            Some((_, '0')) => '\0',
+            Some((_, '\\')) => '\\',
+            Some((_, '"')) => '"',
+            Some((_, '\'')) => '\'',
+            Some((_, '$')) => '$',
+            Some((_, 'u')) => unicode_escape(&mut chars).map_err(|message| (offset, message))?,
+            Some((_, other)) => {
+                let message = format!("Invalid escape sequence '\\{}'.", other.escape_debug());
+                return Err((offset, message));
+            }
+            None => return Err((offset, "Unterminated escape sequence.".to_string())),
+        };
+        out.push(escaped);
+    }
+
+    Ok(out)
+}
+
+fn unicode_escape(chars: &mut std::str::CharIndices) -> Result<char, String> {
+    if chars.next().map(|(_, c)| c) != Some('{') {
+        return Err("Expect '{' after '\\u'.".to_string());
+    }
+
+    let mut digits = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '}')) => break,
+            Some((_, c)) if c.is_ascii_hexdigit() => digits.push(c),
+            _ => return Err("Unicode escape must be hex digits closed by '}'.".to_string()),
+        }
+    }
+
+    if digits.is_empty() || digits.len() > 6 {
+        return Err("Unicode escape must have 1 to 6 hex digits.".to_string());
+    }
+    let code = u32::from_str_radix(&digits, 16).unwrap();
+    char::from_u32(code).ok_or(format!("'\\u{{{}}}' is not a Unicode scalar value.", digits))
+}
+
+// Triple-quoted strings drop the line break right after the opening quotes
+// and the line holding the closing quotes if it is only indentation. The
+// whitespace common to the remaining non-blank lines (and the closing
+// line) is then removed, so a block can be indented with the code around it.
+fn dedent(body: &str) -> String {
+    let mut lines: Vec<&str> = body.split('\n').collect();
+    let first = match lines[0].trim().is_empty() {
+        true => None,
+        false => Some(lines[0]),
+    };
+    lines.remove(0);
+
+    let mut closing = None;
+    if lines.last().is_some_and(|line| line.trim().is_empty()) {
+        closing = lines.pop();
+    }
+
+    let indentation = |line: &&str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    let common = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .chain(closing.iter())
+        .map(indentation)
+        .min()
+        .unwrap_or(0);
+
+    let mut out: Vec<&str> = first.into_iter().collect();
+    for line in lines {
+        out.push(line.get(common..).unwrap_or(line.trim_start()));
+    }
+    out.join("\n")
+}
+
+// Identifiers are UAX #31 default identifiers: an XID_Start character or
+// '_', then any number of XID_Continue characters. They are compared code
+// point for code point, without normalization.
+fn is_identifier_start(c: char) -> bool {
+    c == '_' || is_xid_start(c)
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    is_xid_continue(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<(TokenType, String)> {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut out = Vec::new();
+        loop {
+            let token = scanner.scan_token();
+            if token.token_type == TokenType::EOF {
+                return out;
+            }
+            out.push((token.token_type, token.lexeme));
+        }
+    }
+
+    #[test]
+    fn scans_unicode_identifiers() {
+        for name in [
+            "ไม่",
+            "café",
+            "naïve",
+            "Ωmega",
+            "_x1",
+            "x\u{0301}",
+            "a·b",
+            "下划线_",
+        ] {
+            let source = format!("var {} = 1;", name);
+            assert_eq!(
+                tokens(&source)[1],
+                (TokenType::Identifier, name.to_string()),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_characters_outside_xid() {
+        let scanned = tokens("var x² = 4;");
+        assert_eq!(scanned[1], (TokenType::Identifier, "x".to_string()));
+        assert_eq!(
+            scanned[2],
+            (TokenType::Error, "Unexpected character.".to_string())
+        );
+
+        for source in ["\u{0301}x", "²", "€", "😀"] {
+            assert_eq!(
+                tokens(source)[0],
+                (TokenType::Error, "Unexpected character.".to_string())
+            );
+        }
+    }
+
+    fn string(value: &str) -> (TokenType, String) {
+        (TokenType::String, value.to_string())
+    }
+
+    // The message and line of the first error token.
+    fn error(source: &str) -> (String, usize) {
+        let mut scanner = Scanner::new(source.to_string());
+        loop {
+            let token = scanner.scan_token();
+            match token.token_type {
+                TokenType::Error => return (token.lexeme, token.line),
+                TokenType::EOF => panic!("no error scanning {:?}", source),
+                _ => {}
+            }
+        }
+    }
+
+    #[test]
+    fn processes_escapes() {
+        let cases = [
+            (r#""a\nb""#, "a\nb"),
+            (r#""\t\r\0""#, "\t\r\0"),
+            (r#""\\ \" \' \$""#, "\\ \" ' $"),
+            (r#""\u{41}\u{e9}\u{1F600}""#, "Aé😀"),
+            (r#""\u{000041}""#, "A"),
+            ("\"line\nbreak\"", "line\nbreak"),
+        ];
+        for (source, value) in cases {
+            assert_eq!(tokens(source), [string(value)], "{}", source);
+        }
+    }
+
+    #[test]
+    fn reports_bad_escapes() {
+        let cases = [
+            (r#""\q""#, "Invalid escape sequence '\\q'."),
+            (r#""\u41""#, "Expect '{' after '\\u'."),
+            (
+                r#""\u{4G}""#,
+                "Unicode escape must be hex digits closed by '}'.",
+            ),
+            (
+                r#""\u{41""#,
+                "Unicode escape must be hex digits closed by '}'.",
+            ),
+            (r#""\u{}""#, "Unicode escape must have 1 to 6 hex digits."),
+            (
+                r#""\u{1000000}""#,
+                "Unicode escape must have 1 to 6 hex digits.",
+            ),
+            (
+                r#""\u{D800}""#,
+                "'\\u{D800}' is not a Unicode scalar value.",
+            ),
+            (
+                r#""\u{110000}""#,
+                "'\\u{110000}' is not a Unicode scalar value.",
+            ),
+        ];
+        for (source, message) in cases {
+            assert_eq!(error(source), (message.to_string(), 1), "{}", source);
+        }
+        // The error is reported on the line of the escape, not the string's.
+        assert_eq!(
+            error("\"a\n\nb\\qc\""),
+            ("Invalid escape sequence '\\q'.".to_string(), 3)
+        );
+    }
+
+    #[test]
+    fn reports_unterminated_strings() {
+        for source in [
+            "\"abc",
+            "\"abc\\\"",
+            "\"abc\\",
+            "\"\"\"abc\"\"",
+            "r\"abc",
+            "r#\"abc\"",
+            "r#\"\"\"a\"\"\"",
+        ] {
+            assert_eq!(error(source).0, "Unterminated string.", "{}", source);
+        }
+    }
+
+    #[test]
+    fn scans_raw_strings() {
+        let cases = [
+            (r#"r"a\nb""#, r"a\nb"),
+            (r#"r"\q \u{zz}""#, r"\q \u{zz}"),
+            (r#"r"${x}""#, "${x}"),
+            (r##"r#"say "hi""#"##, r#"say "hi""#),
+            (r###"r##"a "# b"##"###, r##"a "# b"##),
+            (r#"r"""#, ""),
+        ];
+        for (source, value) in cases {
+            assert_eq!(tokens(source), [string(value)], "{}", source);
+        }
+        // `r` not followed by a quote is an identifier.
+        assert_eq!(tokens("r #")[0], (TokenType::Identifier, "r".to_string()));
+    }
+
+    #[test]
+    fn dedents_triple_quoted_blocks() {
+        let cases = [
+            ("\"\"\"\n    a\n      b\n    \"\"\"", "a\n  b"),
+            ("\"\"\"\n    a\n\n    b\n    \"\"\"", "a\n\nb"),
+            ("\"\"\"\n\ta\n\t\"\"\"", "a"),
+            ("\"\"\"first\n    second\"\"\"", "first\nsecond"),
+            ("\"\"\"\n      a\n    \"\"\"", "  a"),
+            ("\"\"\"\n    a \"quoted\" b\n    \"\"\"", "a \"quoted\" b"),
+            ("\"\"\"\n    a\\tb\\u{41}\n    \"\"\"", "a\tbA"),
+            ("\"\"\"${x}\"\"\"", "${x}"),
+            ("r\"\"\"\n    a\\n\n    \"\"\"", "a\\n"),
+            ("\"\"\"\"\"\"", ""),
+        ];
+        for (source, value) in cases {
+            assert_eq!(tokens(source), [string(value)], "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn counts_lines_inside_strings() {
+        let mut scanner = Scanner::new("\"\"\"\n  a\n  \"\"\" x".to_string());
+        scanner.scan_token();
+        assert_eq!(scanner.scan_token().line, 3);
+    }
+
+    fn token(token_type: TokenType, lexeme: &str) -> (TokenType, String) {
+        (token_type, lexeme.to_string())
+    }
+
+    #[test]
+    fn splits_interpolated_strings() {
+        use TokenType::*;
+        assert_eq!(
+            tokens(r#""a ${x} b""#),
+            [
+                token(Interpolation, "a "),
+                token(Identifier, "x"),
+                string(" b")
+            ]
+        );
+        assert_eq!(
+            tokens(r#""${x}${y}""#),
+            [
+                token(Interpolation, ""),
+                token(Identifier, "x"),
+                token(Interpolation, ""),
+                token(Identifier, "y"),
+                string("")
+            ]
+        );
+        // Braces inside the expression don't end it.
+        assert_eq!(
+            tokens(r#""${ {1: 2}[1] }!""#),
+            [
+                token(Interpolation, ""),
+                token(LeftBrace, "{"),
+                token(Number, "1"),
+                token(Colon, ":"),
+                token(Number, "2"),
+                token(RightBrace, "}"),
+                token(LeftBracket, "["),
+                token(Number, "1"),
+                token(RightBracket, "]"),
+                string("!")
+            ]
+        );
+        // Strings nest inside interpolations.
+        assert_eq!(
+            tokens(r#""a ${"b ${c}"} d""#),
+            [
+                token(Interpolation, "a "),
+                token(Interpolation, "b "),
+                token(Identifier, "c"),
+                string(""),
+                string(" d")
+            ]
+        );
+    }
+
+    #[test]
+    fn escapes_interpolation() {
+        assert_eq!(tokens(r#""\${x}""#), [string("${x}")]);
+        assert_eq!(tokens(r#""$x {y}""#), [string("$x {y}")]);
+        assert_eq!(
+            tokens(r#""\t${x}\n""#),
+            [
+                token(TokenType::Interpolation, "\t"),
+                token(TokenType::Identifier, "x"),
+                string("\n")
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_bad_interpolations() {
+        assert_eq!(error(r#""\q ${x}""#).0, "Invalid escape sequence '\\q'.");
+        assert_eq!(error(r#""${x} \q""#).0, "Invalid escape sequence '\\q'.");
+        assert_eq!(error(r#""a ${x} b"#).0, "Unterminated string.");
+        assert_eq!(error(r#""a ${"b"#).0, "Unterminated string.");
+        // Without its closing brace the string never resumes; the parser
+        // reports the missing '}'.
+        assert_eq!(
+            tokens(r#""a ${x"#),
+            [
+                token(TokenType::Interpolation, "a "),
+                token(TokenType::Identifier, "x")
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_number_literals() {
+        for source in [
+            "0",
+            "12",
+            "1_000",
+            "1.5",
+            "0.25",
+            "2.5e-3",
+            "1E10",
+            "1e+5",
+            "6.02e2_3",
+            "0xFF",
+            "0XfF",
+            "0xdead_beef",
+            "0b1010",
+            "0B1_0",
+            "0o17",
+            "0O7_7",
+        ] {
+            assert_eq!(
+                tokens(source),
+                [token(TokenType::Number, source)],
+                "{}",
+                source
+            );
+        }
+        // A '.' not followed by a digit is not part of the number.
+        assert_eq!(
+            tokens("1.abs"),
+            [
+                token(TokenType::Number, "1"),
+                token(TokenType::Dot, "."),
+                token(TokenType::Identifier, "abs")
+            ]
+        );
+        assert_eq!(tokens("0..3")[0], token(TokenType::Number, "0"));
+        assert_eq!(
+            tokens("1-2"),
+            [
+                token(TokenType::Number, "1"),
+                token(TokenType::Minus, "-"),
+                token(TokenType::Number, "2")
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_malformed_numbers() {
+        let cases = [
+            ("0x", "Expect digits in hex literal."),
+            ("0b", "Expect digits in binary literal."),
+            ("0xG", "Invalid digit 'G' in hex literal."),
+            ("0b102", "Invalid digit '2' in binary literal."),
+            ("0o8", "Invalid digit '8' in octal literal."),
+            ("0x_F", "'_' in a hex literal must sit between digits."),
+            ("0b1__0", "'_' in a binary literal must sit between digits."),
+            ("1_", "'_' in a number literal must sit between digits."),
+            ("1__0", "'_' in a number literal must sit between digits."),
+            ("1_.5", "'_' in a number literal must sit between digits."),
+            ("1.5_", "'_' in a number literal must sit between digits."),
+            ("12abc", "Invalid character 'a' in number literal."),
+            ("1.2.3", "Number literal has more than one decimal point."),
+            ("1e", "Expect digits after the exponent in number literal."),
+            ("1e+", "Expect digits after the exponent in number literal."),
+            ("1e5e3", "Number literal has more than one exponent."),
+            ("1e1.5", "Exponent in number literal must be an integer."),
+        ];
+        for (source, message) in cases {
+            assert_eq!(error(source), (message.to_string(), 1), "{}", source);
+        }
+    }
+
+    #[test]
+    fn passes_on_the_brace_of_an_empty_interpolation() {
+        assert_eq!(
+            tokens(r#""a ${ } b""#),
+            [
+                token(TokenType::Interpolation, "a "),
+                token(TokenType::RightBrace, "}"),
+                string(" b")
+            ]
+        );
+        assert_eq!(
+            tokens(r#""${}${x}""#),
+            [
+                token(TokenType::Interpolation, ""),
+                token(TokenType::RightBrace, "}"),
+                token(TokenType::Interpolation, ""),
+                token(TokenType::Identifier, "x"),
+                string("")
+            ]
+        );
+    }
+
+    // Token types with their lines.
+    fn lines(source: &str) -> Vec<(TokenType, usize)> {
+        let mut scanner = Scanner::new(source.to_string());
+        let mut out = Vec::new();
+        loop {
+            let token = scanner.scan_token();
+            if token.token_type == TokenType::EOF {
+                return out;
+            }
+            out.push((token.token_type, token.line));
+        }
+    }
+
+    #[test]
+    fn skips_nested_block_comments() {
+        use TokenType::*;
+        assert_eq!(
+            tokens("a /* x /* y */ z */ b"),
+            [token(Identifier, "a"), token(Identifier, "b")]
+        );
+        assert_eq!(tokens("/**/x/***/"), [token(Identifier, "x")]);
+        assert_eq!(
+            tokens("1 /* c */ * 2"),
+            [token(Number, "1"), token(Star, "*"), token(Number, "2")]
+        );
+        // Line comments and doc comments inside a block comment are text.
+        assert_eq!(tokens("/* // */ x"), [token(Identifier, "x")]);
+        assert_eq!(tokens("/* /// doc */ x"), [token(Identifier, "x")]);
+        // A block comment inside a line comment is not opened.
+        assert_eq!(tokens("// /*\nx"), [token(Identifier, "x")]);
+        // A stray `*/` is just operators.
+        assert_eq!(tokens("*/"), [token(Star, "*"), token(Slash, "/")]);
+    }
+
+    #[test]
+    fn counts_lines_inside_block_comments() {
+        assert_eq!(
+            lines("a /* one\ntwo /* three\n */\n*/ b\nc"),
+            [
+                (TokenType::Identifier, 1),
+                (TokenType::Identifier, 4),
+                (TokenType::Identifier, 5)
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_unterminated_block_comments() {
+        for source in ["/*", "/* a", "/* /* */", "x /* a\n\n*"] {
+            let line = source.matches('\n').count() + 1;
+            assert_eq!(
+                error(source),
+                ("Unterminated comment.".to_string(), line),
+                "{:?}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn scans_doc_comments() {
+        use TokenType::*;
+        assert_eq!(
+            tokens("/// Adds one.\nvar x;"),
+            [
+                token(DocComment, " Adds one."),
+                token(Var, "var"),
+                token(Identifier, "x"),
+                token(Semicolon, ";")
+            ]
+        );
+        assert_eq!(tokens("///"), [token(DocComment, "")]);
+        assert_eq!(
+            tokens("x ///after"),
+            [token(Identifier, "x"), token(DocComment, "after")]
+        );
+        // Only exactly three slashes start a doc comment.
+        assert_eq!(tokens("// plain\n//// ruler\nx"), [token(Identifier, "x")]);
+        assert_eq!(
+            lines("\n/// one\n/// two\ny"),
+            [(DocComment, 2), (DocComment, 3), (Identifier, 4)]
+        );
+    }
+
+    #[test]
+    fn scans_floor_division_after_operands() {
+        use TokenType::*;
+        for source in [
+            "a // 2",
+            "1//2",
+            "\"s\" // 2",
+            "(a) // 2",
+            "[a] // 2",
+            "nil // 2",
+            "a /* */ // 2",
+        ] {
+            assert!(
+                tokens(source).contains(&token(SlashSlash, "//")),
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            tokens("a //= 2"),
+            [
+                token(Identifier, "a"),
+                token(SlashSlashEqual, "//="),
+                token(Number, "2")
+            ]
+        );
+        assert_eq!(
+            tokens(r#""${a // 2}""#),
+            [
+                token(Interpolation, ""),
+                token(Identifier, "a"),
+                token(SlashSlash, "//"),
+                token(Number, "2"),
+                string("")
+            ]
+        );
+    }
+
+    #[test]
+    fn scans_comments_everywhere_else() {
+        use TokenType::*;
+        let x = || token(Identifier, "x");
+        let cases = [
+            ("// start", vec![]),
+            ("x; // after a semicolon", vec![x(), token(Semicolon, ";")]),
+            ("x = // after an operator", vec![x(), token(Equal, "=")]),
+            ("(// after a paren", vec![token(LeftParen, "(")]),
+            (
+                "{} // after a brace",
+                vec![token(LeftBrace, "{"), token(RightBrace, "}")],
+            ),
+            ("x //// ruler", vec![x()]),
+            ("x\n// next line\n+", vec![x(), token(Plus, "+")]),
+            ("x /// doc", vec![x(), token(DocComment, " doc")]),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(tokens(source), expected, "{}", source);
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use crate::bigint::BigInt;
+use crate::chunk::*;
+use crate::value::*;
+use std::fmt::{Display, Formatter};
+
+// Layout of an `.arcb` file, all integers little-endian:
+//
+//   magic      4 bytes   "ARCB"
+//   version    u16
+//   constants  u32 count, then one tagged value each
+//   code       u32 length, then the raw bytecode
+//   lines      u32 run count, then (line: u32, length: u32) pairs
+//   handlers   since version 4: u32 count, then (start, end, target,
+//              depth) as u32s each
+//   functions  u32 count of nested prototypes (always 0 for now)
+pub const MAGIC: &[u8; 4] = b"ARCB";
+pub const FORMAT_VERSION: u16 = 4;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+// Since version 2: an i64.
+const TAG_INT: u8 = 5;
+// Since version 3: u32 length, then decimal digits with an optional '-'.
+const TAG_BIGINT: u8 = 6;
+
+#[derive(Debug)]
+pub enum LoadError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    UnexpectedEof,
+    UnknownConstantTag(u8),
+    InvalidString,
+    InvalidInteger,
+    LineTableMismatch { code: usize, lines: usize },
+    UnexpectedFunctions(usize),
+    TrailingBytes(usize),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::BadMagic => write!(f, "not an arc bytecode file"),
+            LoadError::UnsupportedVersion(v) => write!(
+                f,
+                "unsupported bytecode version {} (this build reads up to {})",
+                v, FORMAT_VERSION
+            ),
+            LoadError::UnexpectedEof => write!(f, "unexpected end of file"),
+            LoadError::UnknownConstantTag(t) => write!(f, "unknown constant tag {}", t),
+            LoadError::InvalidString => write!(f, "string constant is not valid UTF-8"),
+            LoadError::InvalidInteger => write!(f, "integer constant is malformed"),
+            LoadError::LineTableMismatch { code, lines } => {
+                write!(f, "line table covers {} bytes but code has {}", lines, code)
+            }
+            LoadError::UnexpectedFunctions(n) => {
+                write!(
+                    f,
+                    "{} function prototypes, but functions are not supported",
+                    n
+                )
+            }
+            LoadError::TrailingBytes(n) => write!(f, "{} trailing bytes after chunk", n),
+        }
+    }
+}
+
+impl Chunk {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        self.write_body(&mut out);
+        out
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Chunk, LoadError> {
+        let mut reader = Reader { bytes, pos: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(LoadError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version == 0 || version > FORMAT_VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+
+        let chunk = Chunk::read_body(&mut reader, version)?;
+        if reader.pos != bytes.len() {
+            return Err(LoadError::TrailingBytes(bytes.len() - reader.pos));
+        }
+        Ok(chunk)
+    }
+
+    fn write_body(&self, out: &mut Vec<u8>) {
+        write_u32(out, self.constants.values.len());
+        for value in &self.constants.values {
+            write_value(out, value);
+        }
+
+        write_u32(out, self.code.len());
+        out.extend_from_slice(&self.code);
+
+        let runs = line_runs(&self.lines);
+        write_u32(out, runs.len());
+        for (line, length) in runs {
+            write_u32(out, line);
+            write_u32(out, length);
+        }
+
+        write_u32(out, self.handlers.len());
+        for handler in &self.handlers {
+            write_u32(out, handler.start);
+            write_u32(out, handler.end);
+            write_u32(out, handler.target);
+            write_u32(out, handler.depth);
+        }
+
+        write_u32(out, 0);
+    }
+
+    fn read_body(reader: &mut Reader, version: u16) -> Result<Chunk, LoadError> {
+        let mut chunk = Chunk::new();
+
+        let constants = reader.u32()?;
+        for _ in 0..constants {
+            let value = reader.value()?;
+            chunk.constants.write(value);
+        }
+
+        let code = reader.u32()?;
+        chunk.code = reader.take(code)?.to_vec();
+
+        // Run lengths come from the file, so they are checked against the
+        // code before any are expanded.
+        let runs = reader.u32()?;
+        for _ in 0..runs {
+            let line = reader.u32()?;
+            let length = reader.u32()?;
+            let covered = chunk.lines.len();
+            if covered
+                .checked_add(length)
+                .is_none_or(|total| total > chunk.code.len())
+            {
+                return Err(LoadError::LineTableMismatch {
+                    code: chunk.code.len(),
+                    lines: covered.saturating_add(length),
+                });
+            }
+            chunk.lines.extend(std::iter::repeat_n(line, length));
+        }
+        if chunk.lines.len() != chunk.code.len() {
+            return Err(LoadError::LineTableMismatch {
+                code: chunk.code.len(),
+                lines: chunk.lines.len(),
+            });
+        }
+
+        if version >= 4 {
+            let handlers = reader.u32()?;
+            for _ in 0..handlers {
+                chunk.handlers.push(Handler {
+                    start: reader.u32()?,
+                    end: reader.u32()?,
+                    target: reader.u32()?,
+                    depth: reader.u32()?,
+                });
+            }
+        }
+
+        let functions = reader.u32()?;
+        if functions != 0 {
+            return Err(LoadError::UnexpectedFunctions(functions));
+        }
+
+        Ok(chunk)
+    }
+}
+
+fn line_runs(lines: &[usize]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for &line in lines {
+        match runs.last_mut() {
+            Some((last, length)) if *last == line => *length += 1,
+            _ => runs.push((line, 1)),
+        }
+    }
+    runs
+}
+
+fn write_u32(out: &mut Vec<u8>, n: usize) {
+    let n = u32::try_from(n).expect("chunk section too large to serialize");
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Nil => out.push(TAG_NIL),
+        Value::Boolean(false) => out.push(TAG_FALSE),
+        Value::Boolean(true) => out.push(TAG_TRUE),
+        Value::Number(n) => {
+            out.push(TAG_NUMBER);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Value::Int(n) => {
+            out.push(TAG_INT);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Value::BigInt(n) => {
+            let digits = n.to_string();
+            out.push(TAG_BIGINT);
+            write_u32(out, digits.len());
+            out.extend_from_slice(digits.as_bytes());
+        }
+        Value::Str(s) => {
+            out.push(TAG_STRING);
+            write_u32(out, s.len());
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::List(_)
+        | Value::Map(_)
+        | Value::Range(_)
+        | Value::Iterator(_)
+        | Value::Error(_) => {
+            unreachable!("collections, ranges, iterators and errors are built at runtime, never stored as constants")
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], LoadError> {
+        let end = self.pos.checked_add(n).ok_or(LoadError::UnexpectedEof)?;
+        let slice = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(LoadError::UnexpectedEof)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, LoadError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn value(&mut self) -> Result<Value, LoadError> {
+        match self.u8()? {
+            TAG_NIL => Ok(Value::Nil),
+            TAG_FALSE => Ok(Value::Boolean(false)),
+            TAG_TRUE => Ok(Value::Boolean(true)),
+            TAG_NUMBER => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                Ok(Value::Number(f64::from_le_bytes(bytes)))
+            }
+            TAG_INT => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                Ok(Value::Int(i64::from_le_bytes(bytes)))
+            }
+            TAG_BIGINT => {
+                let length = self.u32()?;
+                let text = std::str::from_utf8(self.take(length)?)
+                    .map_err(|_| LoadError::InvalidInteger)?;
+                let (negative, digits) = match text.strip_prefix('-') {
+                    Some(digits) => (true, digits),
+                    None => (false, text),
+                };
+                let n = BigInt::parse(digits, 10).ok_or(LoadError::InvalidInteger)?;
+                Ok(Value::integer(if negative { -&n } else { n }))
+            }
+            TAG_STRING => {
+                let length = self.u32()?;
+                let bytes = self.take(length)?;
+                String::from_utf8(bytes.to_vec())
+                    .map(Value::Str)
+                    .map_err(|_| LoadError::InvalidString)
+            }
+            tag => Err(LoadError::UnknownConstantTag(tag)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    const PROGRAM: &str = r#"
+        var greeting = "tab\there \u{1F600}";
+        const BIG = 123456789012345678901234567890;
+        var xs = [1, 2.5, nil, true, "s"];
+        var m = {"a": 1, 2: xs};
+        for x in 0..3 {
+            try {
+                match x {
+                    0 => print greeting;
+                    n if n > 1 => break;
+                    _ => continue;
+                }
+            } finally {
+                print x * BIG;
+            }
+        }
+        try {
+            throw m["a"];
+        } catch (e) {
+            print e.message();
+        } finally {
+            print xs[-1] ?? "none";
+        }
+    "#;
+
+    fn compile(source: &str) -> Chunk {
+        let mut chunk = Chunk::new();
+        assert!(!Compiler::new(&mut chunk).compile(source.to_string()));
+        chunk
+    }
+
+    fn assert_same(a: &Chunk, b: &Chunk) {
+        assert_eq!(a.code, b.code);
+        assert_eq!(a.lines, b.lines);
+        assert_eq!(a.handlers, b.handlers);
+        let literals = |chunk: &Chunk| -> Vec<String> {
+            chunk.constants.values.iter().map(Value::literal).collect()
+        };
+        assert_eq!(literals(a), literals(b));
+    }
+
+    #[test]
+    fn round_trips_compiled_chunk() {
+        let chunk = compile(PROGRAM);
+        assert!(!chunk.handlers.is_empty());
+        let loaded = Chunk::deserialize(&chunk.serialize()).unwrap();
+        assert_same(&chunk, &loaded);
+        assert_eq!(loaded.serialize(), chunk.serialize());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut bytes = compile("print 1;").serialize();
+        bytes[0] = b'X';
+        assert!(matches!(
+            Chunk::deserialize(&bytes),
+            Err(LoadError::BadMagic)
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in [0, FORMAT_VERSION + 1] {
+            let mut bytes = compile("print 1;").serialize();
+            bytes[4..6].copy_from_slice(&version.to_le_bytes());
+            assert!(matches!(
+                Chunk::deserialize(&bytes),
+                Err(LoadError::UnsupportedVersion(v)) if v == version
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_input_at_every_length() {
+        let bytes = compile(PROGRAM).serialize();
+        for length in 0..bytes.len() {
+            assert!(
+                Chunk::deserialize(&bytes[..length]).is_err(),
+                "loaded a file cut to {} bytes",
+                length
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        let mut bytes = compile("print 1;").serialize();
+        bytes.push(0);
+        assert!(matches!(
+            Chunk::deserialize(&bytes),
+            Err(LoadError::TrailingBytes(1))
+        ));
+    }
+
+    // A version 4 file holding `code`, with the given line runs.
+    fn file(code: &[u8], runs: &[(u32, u32)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&4u16.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(code.len() as u32).to_le_bytes());
+        out.extend_from_slice(code);
+        out.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+        for (line, length) in runs {
+            out.extend_from_slice(&line.to_le_bytes());
+            out.extend_from_slice(&length.to_le_bytes());
+        }
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn loads_matching_line_table() {
+        let chunk = Chunk::deserialize(&file(&[OpCode::OpReturn.into()], &[(1, 1)])).unwrap();
+        assert_eq!(chunk.lines, vec![1]);
+    }
+
+    #[test]
+    fn rejects_oversized_line_runs_before_expanding_them() {
+        let bytes = file(&[OpCode::OpReturn.into()], &[(1, u32::MAX), (2, u32::MAX)]);
+        assert!(matches!(
+            Chunk::deserialize(&bytes),
+            Err(LoadError::LineTableMismatch { code: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_short_line_table() {
+        let bytes = file(&[OpCode::OpNil.into(), OpCode::OpReturn.into()], &[(1, 1)]);
+        assert!(matches!(
+            Chunk::deserialize(&bytes),
+            Err(LoadError::LineTableMismatch { code: 2, lines: 1 })
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_line_table() {
+        let mut bytes = file(&[OpCode::OpReturn.into()], &[(1, 1)]);
+        // Drop the functions and handlers counts and half of the run.
+        bytes.truncate(bytes.len() - 12);
+        assert!(matches!(
+            Chunk::deserialize(&bytes),
+            Err(LoadError::UnexpectedEof)
+        ));
+    }
+}
@@ -7,7 +7,7 @@ pub struct Token {
 impl Clone for Token {
     fn clone(&self) -> Self {
         Self {
-            token_type: self.token_type.clone(),
+            token_type: self.token_type,
             lexeme: self.lexeme.clone(),
             line: self.line,
         }
@@ -21,46 +21,98 @@ impl PartialEq for Token {
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum TokenType {
     LeftParen = 0,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    // `..` and `..=`, building ranges.
+    DotDot,
+    DotDotEqual,
     Minus,
     Plus,
     Semicolon,
+    Colon,
+    Question,
+    QuestionQuestion,
+    QuestionDot,
     Slash,
     Star,
+    Percent,
+    StarStar,
+    // `//`, floor division. After the end of an operand on the same
+    // line `//` is this operator; anywhere else it starts a comment.
+    SlashSlash,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     Bang,
     BangEqual,
     Equal,
     EqualEqual,
+    // `=>`, between a match arm's pattern and its body.
+    EqualGreater,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
+
+    // Compound assignment, one per binary operator.
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    StarStarEqual,
+    SlashSlashEqual,
+    AmpersandEqual,
+    PipeEqual,
+    CaretEqual,
+    LessLessEqual,
+    GreaterGreaterEqual,
+    PlusPlus,
+    MinusMinus,
 
     Identifier,
     String,
+    // A string segment that ends in `${`, starting an interpolated expression.
+    Interpolation,
     Number,
+    // The text of a `///` comment, after the slashes.
+    DocComment,
 
     And,
+    Break,
+    Catch,
     Class,
+    Const,
+    Continue,
     Else,
     False,
+    Finally,
     Fn,
     For,
     If,
+    In,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -1,114 +1,509 @@
-use std::ops::{Add, Sub, Div, Mul, Neg};
+use crate::bigint::BigInt;
+use crate::error::*;
+use crate::iter::{Iter, Range};
+use crate::map::Map;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Rem, Shl, Shr, Sub};
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::fmt::{Display, Formatter};
+use std::rc::Rc;
 
-#[derive(Clone, PartialOrd, Debug)]
+#[derive(Clone, Debug)]
 pub enum Value{
     Boolean(bool),
     Number(f64),
+    Int(i64),
+    // Only for integers outside the i64 range; results that fit in an
+    // i64 are always an Int.
+    BigInt(BigInt),
     Str(String),
+    // Lists are shared: copying the value copies the reference.
+    List(Rc<ListCell>),
+    Map(Rc<MapCell>),
+    Range(Range),
+    // The hidden state of a `for` loop.
+    Iterator(Rc<RefCell<Iter>>),
+    // A raised error, as bound by `catch`.
+    Error(Rc<ErrorObject>),
     Nil,
 }
 
+pub type ListCell = RefCell<Vec<Value>>;
+pub type MapCell = RefCell<Map>;
+
 impl Value {
+    // Ints and floats are both numbers; arithmetic between them promotes
+    // the int to a float.
     pub fn is_number(&self) -> bool {
+        matches!(self, Value::Number(_) | Value::Int(_) | Value::BigInt(_))
+    }
+
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Boolean(false))
+    }
+
+    pub fn is_string(&self) -> bool {
+        matches!(self, Value::Str(_))
+    }
+
+    // Like Display, but strings are quoted and escaped so the result can be
+    // read back unambiguously (the disassembler and assembler rely on this).
+    // Floats with no fractional part keep a `.0` to tell them from ints.
+    pub fn literal(&self) -> String {
         match self {
-            Value::Number(_) => true,
-            _ => false,
+            Value::Str(s) => format!("{:?}", s),
+            Value::Number(n) if n.is_finite() && n.fract() == 0.0 => format!("{}.0", n),
+            _ => self.to_string(),
         }
     }
 
-    pub fn is_falsey(&self) -> bool {
+    pub fn list(items: Vec<Value>) -> Value {
+        Value::List(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn map(map: Map) -> Value {
+        Value::Map(Rc::new(RefCell::new(map)))
+    }
+
+    // The Int or BigInt holding an integer result.
+    pub fn integer(n: BigInt) -> Value {
+        match n.to_i64() {
+            Some(n) => Value::Int(n),
+            None => Value::BigInt(n),
+        }
+    }
+
+    fn to_bigint(&self) -> Option<BigInt> {
         match self {
-            Value::Nil => true,
-            Value::Boolean(false) => true,
-            _ => false,
+            Value::Int(n) => Some(BigInt::from(*n)),
+            Value::BigInt(n) => Some(n.clone()),
+            _ => None,
         }
     }
 
-    pub fn is_string(&self) -> bool {
-        matches!(self, Value::Str(_))
+    fn to_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Int(n) => Some(*n as f64),
+            Value::BigInt(n) => Some(n.to_f64()),
+            _ => None,
+        }
     }
 }
 
+// Numbers compare by their mathematical value, so `1 == 1.0` and a BigInt
+// is never equal to an Int. Values of unrelated types are never equal and
+// have no ordering.
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Value::Boolean(a), Value::Boolean(b)) => a == b,
-            (Value::Number(a), Value::Number(b)) => a == b,
-            (Value::Nil, Value::Nil) => true,
-            (Value::Str(a), Value::Str(b)) => a == b,
-            _ => panic!(),
+        equal(self, other, &mut Vec::new())
+    }
+}
+
+// Lists compare element by element, and maps hold equal values under the
+// same keys, in any order. Either may contain itself, so `seen` holds the
+// pairs of containers already being compared further up: meeting one again
+// adds nothing new to check, and the pair counts as equal.
+fn equal(a: &Value, b: &Value, seen: &mut Vec<(*const (), *const ())>) -> bool {
+    match (a, b) {
+        (Value::Boolean(a), Value::Boolean(b)) => a == b,
+        (Value::Nil, Value::Nil) => true,
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::List(a), Value::List(b)) => {
+            Rc::ptr_eq(a, b)
+                || nested(Rc::as_ptr(a).cast(), Rc::as_ptr(b).cast(), seen, |seen| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| equal(a, b, seen))
+                })
+        }
+        (Value::Map(a), Value::Map(b)) => {
+            Rc::ptr_eq(a, b)
+                || nested(Rc::as_ptr(a).cast(), Rc::as_ptr(b).cast(), seen, |seen| {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    a.entries().len() == b.entries().len()
+                        && a.entries().iter().all(|(key, value)| {
+                            matches!(b.get(key), Ok(Some(other)) if equal(value, other, seen))
+                        })
+                })
         }
+        (Value::Range(a), Value::Range(b)) => a == b,
+        (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+        (Value::Error(a), Value::Error(b)) => Rc::ptr_eq(a, b),
+        _ => a.partial_cmp(b) == Some(Ordering::Equal),
     }
 }
 
-impl Add for Value {
-    type Output = Self;
+fn nested(
+    a: *const (),
+    b: *const (),
+    seen: &mut Vec<(*const (), *const ())>,
+    compare: impl FnOnce(&mut Vec<(*const (), *const ())>) -> bool,
+) -> bool {
+    if seen.contains(&(a, b)) {
+        return true;
+    }
+    seen.push((a, b));
+    let equal = compare(seen);
+    seen.pop();
+    equal
+}
 
-    fn add(self, other: Self) -> Self {
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
-            _ => panic!("Operands must be two numbers or two booleans."),
+            (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+            (Value::Number(a), _) if other.is_number() => {
+                compare_integer_float(&other.to_bigint()?, *a).map(Ordering::reverse)
+            }
+            (_, Value::Number(b)) if self.is_number() => {
+                compare_integer_float(&self.to_bigint()?, *b)
+            }
+            (Value::Str(a), Value::Str(b)) => a.partial_cmp(b),
+            _ if self.is_number() && other.is_number() => {
+                self.to_bigint()?.partial_cmp(&other.to_bigint()?)
+            }
+            _ => None,
         }
     }
 }
 
-impl Sub for Value {
-    type Output = Self;
+// Exact, unlike converting the integer to a float, which rounds above 2^53.
+fn compare_integer_float(a: &BigInt, b: f64) -> Option<Ordering> {
+    if b.is_nan() {
+        return None;
+    }
+    if b.is_infinite() {
+        return Some(if b > 0.0 { Ordering::Less } else { Ordering::Greater });
+    }
+    let whole = b.trunc();
+    let ordering = a.cmp(&BigInt::from_f64(whole)?);
+    Some(ordering.then(0.0.partial_cmp(&(b - whole)).unwrap()))
+}
 
-    fn sub(self, other: Self) -> Self {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a - b),
-            _ => panic!("Operands must be two numbers."),
+// Integer arithmetic is exact: an Int result that overflows is redone with
+// BigInts. An integer mixed with a float is converted to a float first.
+fn arithmetic(
+    a: Value,
+    b: Value,
+    int: fn(i64, i64) -> Option<i64>,
+    big: fn(&BigInt, &BigInt) -> BigInt,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value, RuntimeError> {
+    if let (Value::Int(x), Value::Int(y)) = (&a, &b) {
+        if let Some(n) = int(*x, *y) {
+            return Ok(Value::Int(n));
         }
     }
+    if let (Some(x), Some(y)) = (a.to_bigint(), b.to_bigint()) {
+        return Ok(Value::integer(big(&x, &y)));
+    }
+    match (a.to_f64(), b.to_f64()) {
+        (Some(x), Some(y)) => Ok(Value::Number(float(x, y))),
+        _ => Err(type_error("Operands must be two numbers.")),
+    }
+}
+
+impl Add for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn add(self, other: Self) -> Self::Output {
+        arithmetic(self, other, i64::checked_add, |a, b| a + b, |a, b| a + b)
+    }
+}
+
+impl Sub for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn sub(self, other: Self) -> Self::Output {
+        arithmetic(self, other, i64::checked_sub, |a, b| a - b, |a, b| a - b)
+    }
 }
 
+// Dividing two integers truncates toward zero.
 impl Div for Value {
-    type Output = Self;
+    type Output = Result<Value, RuntimeError>;
 
-    fn div(self, other: Self) -> Self {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a / b),
-            _ => panic!("Operands must be two numbers."),
+    fn div(self, other: Self) -> Self::Output {
+        if matches!((&self, &other), (Value::Int(_) | Value::BigInt(_), Value::Int(0))) {
+            return Err(arithmetic_error("Division by zero."));
         }
+        arithmetic(self, other, i64::checked_div, |a, b| a / b, |a, b| a / b)
     }
 }
 
 impl Neg for Value {
-    type Output = Self;
+    type Output = Result<Value, RuntimeError>;
 
-    fn neg(self) -> Self {
+    fn neg(self) -> Self::Output {
         match self {
-            Value::Number(a) => Value::Number(-a),
-            _ => panic!("Operand must be a number."),
+            Value::Number(a) => Ok(Value::Number(-a)),
+            Value::Int(a) => Ok(match a.checked_neg() {
+                Some(n) => Value::Int(n),
+                None => Value::integer(-&BigInt::from(a)),
+            }),
+            Value::BigInt(a) => Ok(Value::integer(-&a)),
+            _ => Err(type_error("Operand must be a number.")),
         }
     }
     
 }
 
 impl Mul for Value {
-    type Output = Self;
+    type Output = Result<Value, RuntimeError>;
 
-    fn mul(self, other: Self) -> Self {
-        match (self, other) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a * b),
-            _ => panic!("Operands must be two numbers."),
-        }
+    fn mul(self, other: Self) -> Self::Output {
+        arithmetic(self, other, i64::checked_mul, |a, b| a * b, |a, b| a * b)
     }
     
 }
 
+// The remainder of floor division: it takes the sign of the divisor, so
+// `-7 % 3 == 2`.
+impl Rem for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn rem(self, other: Self) -> Self::Output {
+        if matches!((&self, &other), (Value::Int(_) | Value::BigInt(_), Value::Int(0))) {
+            return Err(arithmetic_error("Division by zero."));
+        }
+        arithmetic(
+            self,
+            other,
+            |a, b| a.checked_rem(b).map(|r| if r != 0 && (r < 0) != (b < 0) { r + b } else { r }),
+            |a, b| a.div_rem_floor(b).1,
+            |a, b| {
+                let r = a % b;
+                if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                    r + b
+                } else {
+                    r
+                }
+            },
+        )
+    }
+}
+
+// Largest result, in bits, that `**` or `<<` will build before giving up.
+// BigInt multiplies and prints in quadratic time, so this keeps both well
+// under a second: about 39,000 decimal digits.
+pub const MAX_RESULT_BITS: u64 = 1 << 17;
+
+impl Value {
+    // `//`: division rounded toward negative infinity. Integers stay
+    // integers; with a float operand the result is a whole float.
+    pub fn floor_div(self, other: Value) -> Result<Value, RuntimeError> {
+        if matches!((&self, &other), (Value::Int(_) | Value::BigInt(_), Value::Int(0))) {
+            return Err(arithmetic_error("Division by zero."));
+        }
+        arithmetic(
+            self,
+            other,
+            |a, b| {
+                let q = a.checked_div(b)?;
+                Some(if a % b != 0 && (a < 0) != (b < 0) { q - 1 } else { q })
+            },
+            |a, b| a.div_rem_floor(b).0,
+            |a, b| (a / b).floor(),
+        )
+    }
+
+    // `**`: an integer raised to a non-negative integer is exact; anything
+    // else is computed in floats.
+    pub fn pow(self, other: Value) -> Result<Value, RuntimeError> {
+        if let (Some(base), Some(exponent)) = (self.to_bigint(), other.to_bigint()) {
+            if !exponent.is_negative() {
+                if let (Value::Int(base), Value::Int(exponent)) = (&self, &other) {
+                    if let Some(n) = u32::try_from(*exponent).ok().and_then(|e| base.checked_pow(e)) {
+                        return Ok(Value::Int(n));
+                    }
+                }
+                let too_large = || arithmetic_error("Integer result of '**' is too large.");
+                let exponent = match exponent.to_i64().and_then(|e| u32::try_from(e).ok()) {
+                    Some(exponent) => exponent,
+                    // 0, 1 and -1 stay small; only the exponent's parity matters.
+                    None if base.bits() <= 1 => 2 + exponent.div_rem(&BigInt::from(2)).1.bits() as u32,
+                    None => return Err(too_large()),
+                };
+                if base.bits() > 1 && base.bits() * exponent as u64 > MAX_RESULT_BITS {
+                    return Err(too_large());
+                }
+                return Ok(Value::integer(base.pow(exponent)));
+            }
+        }
+        match (self.to_f64(), other.to_f64()) {
+            (Some(a), Some(b)) => Ok(Value::Number(a.powf(b))),
+            _ => Err(type_error("Operands must be two numbers.")),
+        }
+    }
+}
+
+// The bitwise operators work on integers of any size, treating negative
+// numbers as two's complement.
+fn bitwise(
+    a: Value,
+    b: Value,
+    int: fn(i64, i64) -> i64,
+    big: fn(&BigInt, &BigInt) -> BigInt,
+) -> Result<Value, RuntimeError> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Ok(Value::Int(int(a, b))),
+        (a, b) => match (a.to_bigint(), b.to_bigint()) {
+            (Some(a), Some(b)) => Ok(Value::integer(big(&a, &b))),
+            _ => Err(type_error("Operands must be integers.")),
+        },
+    }
+}
+
+impl BitAnd for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn bitand(self, other: Self) -> Self::Output {
+        bitwise(self, other, |a, b| a & b, |a, b| a & b)
+    }
+}
+
+impl BitOr for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn bitor(self, other: Self) -> Self::Output {
+        bitwise(self, other, |a, b| a | b, |a, b| a | b)
+    }
+}
+
+impl BitXor for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn bitxor(self, other: Self) -> Self::Output {
+        bitwise(self, other, |a, b| a ^ b, |a, b| a ^ b)
+    }
+}
+
+// Both shifts are arithmetic: `a << n` is `a * 2**n` and `a >> n` is
+// `a // 2**n`.
+impl Shl for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn shl(self, other: Self) -> Self::Output {
+        let count = shift_count(&self, &other)?;
+        if let Value::Int(a) = self {
+            if count < 64 && (a << count) >> count == a {
+                return Ok(Value::Int(a << count));
+            }
+        }
+        let a = self.to_bigint().unwrap();
+        if !a.is_zero() && a.bits() + count as u64 > MAX_RESULT_BITS {
+            return Err(arithmetic_error("Integer result of '<<' is too large."));
+        }
+        Ok(Value::integer(&a << count.min(MAX_RESULT_BITS as usize)))
+    }
+}
+
+impl Shr for Value {
+    type Output = Result<Value, RuntimeError>;
+
+    fn shr(self, other: Self) -> Self::Output {
+        let count = shift_count(&self, &other)?;
+        match self {
+            Value::Int(a) => Ok(Value::Int(a >> count.min(63))),
+            a => Ok(Value::integer(&a.to_bigint().unwrap() >> count)),
+        }
+    }
+}
+
+// A shift count too large for usize is clamped; shifting by that much
+// either overflows the result limit or shifts every bit out.
+fn shift_count(a: &Value, b: &Value) -> Result<usize, RuntimeError> {
+    let (Some(_), Some(count)) = (a.to_bigint(), b.to_bigint()) else {
+        return Err(type_error("Operands must be integers."));
+    };
+    if count.is_negative() {
+        return Err(value_error("Shift count must not be negative."));
+    }
+    Ok(count
+        .to_i64()
+        .and_then(|count| usize::try_from(count).ok())
+        .unwrap_or(usize::MAX))
+}
+
+impl Value {
+    // `~x`, which is `-x - 1` for integers of any size.
+    pub fn bit_not(self) -> Result<Value, RuntimeError> {
+        match self {
+            Value::Int(a) => Ok(Value::Int(!a)),
+            Value::BigInt(a) => Ok(Value::integer(&-&a - &BigInt::from(1))),
+            _ => Err(type_error("Operand must be an integer.")),
+        }
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
         match self {
             Value::Boolean(b) => write!(f, "{b}"),
             Value::Number(n) => write!(f, "{n}"),
+            Value::Int(n) => write!(f, "{n}"),
+            Value::BigInt(n) => write!(f, "{n}"),
             Value::Nil => write!(f, "nil"),
             Value::Str(s) => write!(f, "{s}"),
+            Value::Range(range) => write!(f, "{range}"),
+            Value::Iterator(_) => write!(f, "<iterator>"),
+            Value::Error(error) => write!(f, "{}", error),
+            Value::List(_) | Value::Map(_) => write_nested(f, self, &mut Vec::new()),
+        }
+    }
+}
+
+// Elements, keys and values are written as literals, so strings are
+// quoted. A list or map that contains itself is written as `[...]` or
+// `{...}` where it recurs.
+fn write_nested(
+    f: &mut Formatter<'_>,
+    value: &Value,
+    open: &mut Vec<*const ()>,
+) -> Result<(), std::fmt::Error> {
+    let identity = match value {
+        Value::List(list) => Rc::as_ptr(list) as *const (),
+        Value::Map(map) => Rc::as_ptr(map) as *const (),
+        value => return write!(f, "{}", value.literal()),
+    };
+    if open.contains(&identity) {
+        return match value {
+            Value::List(_) => write!(f, "[...]"),
+            _ => write!(f, "{{...}}"),
+        };
+    }
+    open.push(identity);
+
+    match value {
+        Value::List(list) => {
+            write!(f, "[")?;
+            for (index, item) in list.borrow().iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write_nested(f, item, open)?;
+            }
+            write!(f, "]")?;
         }
+        Value::Map(map) => {
+            write!(f, "{{")?;
+            for (index, (key, item)) in map.borrow().entries().iter().enumerate() {
+                if index > 0 {
+                    write!(f, ", ")?;
+                }
+                write_nested(f, key, open)?;
+                write!(f, ": ")?;
+                write_nested(f, item, open)?;
+            }
+            write!(f, "}}")?;
+        }
+        _ => unreachable!(),
     }
+    open.pop();
+    Ok(())
 }
+
 pub struct ValueArray {
     pub values: Vec<Value>,
 }
@@ -127,8 +522,106 @@ impl ValueArray {
     pub fn free(&mut self) {
         self.values = Vec::new();
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(items: Vec<Value>) -> Value {
+        Value::list(items)
+    }
+
+    fn push(list: &Value, item: Value) {
+        let Value::List(cell) = list else {
+            panic!("not a list")
+        };
+        cell.borrow_mut().push(item);
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        let mut map = Map::new();
+        for (key, value) in entries {
+            map.insert(Value::Str(key.to_string()), value).unwrap();
+        }
+        Value::map(map)
+    }
+
+    fn insert(map: &Value, key: &str, value: Value) {
+        let Value::Map(cell) = map else {
+            panic!("not a map")
+        };
+        cell.borrow_mut()
+            .insert(Value::Str(key.to_string()), value)
+            .unwrap();
+    }
+
+    #[test]
+    fn compares_lists_and_maps_by_contents() {
+        assert_eq!(
+            list(vec![Value::Int(1), Value::Number(2.0)]),
+            list(vec![Value::Number(1.0), Value::Int(2)])
+        );
+        assert_ne!(
+            list(vec![Value::Int(1)]),
+            list(vec![Value::Int(1), Value::Int(1)])
+        );
+        assert_eq!(
+            map(vec![("a", Value::Int(1)), ("b", Value::Nil)]),
+            map(vec![("b", Value::Nil), ("a", Value::Int(1))])
+        );
+        assert_ne!(
+            map(vec![("a", Value::Int(1))]),
+            map(vec![("b", Value::Int(1))])
+        );
+        assert_ne!(map(vec![("a", Value::Int(1))]), list(vec![Value::Int(1)]));
+    }
+
+    #[test]
+    fn compares_self_containing_lists() {
+        let (a, b) = (list(vec![Value::Int(1)]), list(vec![Value::Int(1)]));
+        push(&a, a.clone());
+        push(&b, b.clone());
+        assert_eq!(a, b);
+        assert_eq!(a, a.clone());
+
+        // [1, [1, [1, ...]]] unrolled one level is still the same list.
+        let c = list(vec![Value::Int(1)]);
+        push(&c, list(vec![Value::Int(1), c.clone()]));
+        assert_eq!(a, c);
+
+        let d = list(vec![Value::Int(2)]);
+        push(&d, d.clone());
+        assert_ne!(a, d);
+
+        // Mutual cycles: e holds f and f holds e.
+        let (e, f) = (list(vec![]), list(vec![]));
+        push(&e, f.clone());
+        push(&f, e.clone());
+        assert_eq!(e, f);
+        let g = list(vec![]);
+        push(&g, g.clone());
+        assert_eq!(e, g);
+        assert_ne!(e, a);
+    }
+
+    #[test]
+    fn compares_self_containing_maps() {
+        let (m, n) = (
+            map(vec![("k", Value::Int(1))]),
+            map(vec![("k", Value::Int(1))]),
+        );
+        insert(&m, "self", m.clone());
+        insert(&n, "self", n.clone());
+        assert_eq!(m, n);
+
+        let l = list(vec![m.clone()]);
+        insert(&m, "list", l.clone());
+        assert_ne!(m, n);
+        insert(&n, "list", list(vec![n.clone()]));
+        assert_eq!(m, n);
 
-    pub fn print_value(&self, index: usize) {
-        print!("{}", self.values[index]);
+        insert(&n, "k", Value::Int(2));
+        assert_ne!(m, n);
     }
 }
@@ -0,0 +1,631 @@
+use crate::chunk::*;
+use crate::value::*;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug)]
+pub enum VerifyError {
+    EmptyChunk,
+    LineTableMismatch {
+        code: usize,
+        lines: usize,
+    },
+    UnknownOpcode {
+        offset: usize,
+        byte: u8,
+    },
+    TruncatedOperand {
+        offset: usize,
+        opcode: OpCode,
+    },
+    ConstantOutOfRange {
+        offset: usize,
+        index: usize,
+        count: usize,
+    },
+    ConstantType {
+        offset: usize,
+        opcode: OpCode,
+        expected: &'static str,
+        found: Value,
+    },
+    JumpOutOfRange {
+        offset: usize,
+        target: usize,
+    },
+    JumpIntoInstruction {
+        offset: usize,
+        target: usize,
+    },
+    JumpBeforeStart {
+        offset: usize,
+    },
+    SlotOutOfRange {
+        offset: usize,
+        slot: usize,
+        depth: usize,
+    },
+    StackUnderflow {
+        offset: usize,
+        opcode: OpCode,
+        depth: usize,
+        needed: usize,
+    },
+    StackMismatch {
+        offset: usize,
+        expected: usize,
+        found: usize,
+    },
+    FallsOffEnd {
+        offset: usize,
+    },
+    HandlerOutOfRange {
+        index: usize,
+    },
+    HandlerIntoInstruction {
+        index: usize,
+        offset: usize,
+    },
+    HandlerDepth {
+        offset: usize,
+        index: usize,
+        depth: usize,
+        needed: usize,
+    },
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyError::EmptyChunk => write!(f, "chunk has no code"),
+            VerifyError::LineTableMismatch { code, lines } => write!(
+                f,
+                "line table has {} entries but code has {} bytes",
+                lines, code
+            ),
+            VerifyError::UnknownOpcode { offset, byte } => {
+                write!(f, "{:04}: unknown opcode {}", offset, byte)
+            }
+            VerifyError::TruncatedOperand { offset, opcode } => {
+                write!(f, "{:04}: {:?} is missing its operand", offset, opcode)
+            }
+            VerifyError::ConstantOutOfRange {
+                offset,
+                index,
+                count,
+            } => write!(
+                f,
+                "{:04}: constant {} out of range, pool has {} entries",
+                offset, index, count
+            ),
+            VerifyError::ConstantType {
+                offset,
+                opcode,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:04}: {:?} expects a {} constant, found '{}'",
+                offset, opcode, expected, found
+            ),
+            VerifyError::JumpOutOfRange { offset, target } => {
+                write!(
+                    f,
+                    "{:04}: jump target {:04} is outside the code",
+                    offset, target
+                )
+            }
+            VerifyError::JumpIntoInstruction { offset, target } => write!(
+                f,
+                "{:04}: jump target {:04} is not an instruction boundary",
+                offset, target
+            ),
+            VerifyError::JumpBeforeStart { offset } => {
+                write!(f, "{:04}: jump target is before the start of the code", offset)
+            }
+            VerifyError::SlotOutOfRange {
+                offset,
+                slot,
+                depth,
+            } => write!(
+                f,
+                "{:04}: local slot {} is not on the stack, which holds {} values",
+                offset, slot, depth
+            ),
+            VerifyError::StackUnderflow {
+                offset,
+                opcode,
+                depth,
+                needed,
+            } => write!(
+                f,
+                "{:04}: {:?} pops {} values but the stack only holds {}",
+                offset, opcode, needed, depth
+            ),
+            VerifyError::StackMismatch {
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{:04}: reached with stack depth {} on one path and {} on another",
+                offset, expected, found
+            ),
+            VerifyError::FallsOffEnd { offset } => {
+                write!(f, "{:04}: execution runs past the end of the code", offset)
+            }
+            VerifyError::HandlerOutOfRange { index } => {
+                write!(
+                    f,
+                    "handler {} covers or targets code outside the chunk",
+                    index
+                )
+            }
+            VerifyError::HandlerIntoInstruction { index, offset } => write!(
+                f,
+                "handler {}: {:04} is not an instruction boundary",
+                index, offset
+            ),
+            VerifyError::HandlerDepth {
+                offset,
+                index,
+                depth,
+                needed,
+            } => write!(
+                f,
+                "{:04}: handler {} keeps {} values but the stack may only hold {}",
+                offset, index, needed, depth
+            ),
+        }
+    }
+}
+
+// Checks the structure of a chunk before it is handed to the VM: every
+// byte decodes to a known instruction with in-range operands of the right
+// type, control never leaves the code, and the value stack cannot
+// underflow on any path, including the paths into error handlers. Only
+// stack depths are tracked, not the kinds of values; the VM reports a
+// value of the wrong kind, such as OpForIter on a non-iterator, as a
+// runtime error.
+pub fn verify(chunk: &Chunk) -> Result<(), VerifyError> {
+    if chunk.code.is_empty() {
+        return Err(VerifyError::EmptyChunk);
+    }
+    if chunk.lines.len() != chunk.code.len() {
+        return Err(VerifyError::LineTableMismatch {
+            code: chunk.code.len(),
+            lines: chunk.lines.len(),
+        });
+    }
+
+    let mut instructions: Vec<Option<Instruction>> = Vec::new();
+    instructions.resize_with(chunk.code.len(), || None);
+
+    for decoded in chunk.instructions() {
+        let instruction = decoded.map_err(|err| match err {
+            DecodeError::UnknownOpcode { offset, byte } => {
+                VerifyError::UnknownOpcode { offset, byte }
+            }
+            DecodeError::TruncatedOperand { offset, opcode } => {
+                VerifyError::TruncatedOperand { offset, opcode }
+            }
+        })?;
+        check_operand(chunk, &instruction)?;
+        let offset = instruction.offset;
+        instructions[offset] = Some(instruction);
+    }
+
+    check_handlers(chunk, &instructions)?;
+    check_stack(chunk, &instructions)
+}
+
+// A handler's range must be whole instructions, and its target the start of
+// one.
+fn check_handlers(chunk: &Chunk, instructions: &[Option<Instruction>]) -> Result<(), VerifyError> {
+    let len = chunk.code.len();
+    for (index, handler) in chunk.handlers.iter().enumerate() {
+        if handler.start > handler.end || handler.end > len || handler.target >= len {
+            return Err(VerifyError::HandlerOutOfRange { index });
+        }
+        for offset in [handler.start, handler.end, handler.target] {
+            if offset < len && instructions[offset].is_none() {
+                return Err(VerifyError::HandlerIntoInstruction { index, offset });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_operand(chunk: &Chunk, instruction: &Instruction) -> Result<(), VerifyError> {
+    let offset = instruction.offset;
+    let kinds = instruction.opcode.info().operands;
+
+    for (&kind, &index) in kinds.iter().zip(&instruction.operands) {
+        if !matches!(kind, Operand::Constant | Operand::Name) {
+            continue;
+        }
+
+        let count = chunk.constants.values.len();
+        if index >= count {
+            return Err(VerifyError::ConstantOutOfRange {
+                offset,
+                index,
+                count,
+            });
+        }
+
+        let constant = &chunk.constants.values[index];
+        if kind == Operand::Name && !constant.is_string() {
+            return Err(VerifyError::ConstantType {
+                offset,
+                opcode: instruction.opcode,
+                expected: "string",
+                found: constant.clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+// Walks every path from the entry point, recording the stack depth each
+// instruction is reached with. Paths that meet must agree on the depth.
+// A handler's target is reached with its depth plus the error.
+fn check_stack(chunk: &Chunk, instructions: &[Option<Instruction>]) -> Result<(), VerifyError> {
+    let mut depths: Vec<Option<usize>> = vec![None; chunk.code.len()];
+    let mut worklist = vec![(0, 0)];
+    for handler in &chunk.handlers {
+        worklist.push((handler.target, handler.depth + 1));
+    }
+
+    while let Some((offset, depth)) = worklist.pop() {
+        match depths[offset] {
+            Some(expected) if expected == depth => continue,
+            Some(expected) => {
+                return Err(VerifyError::StackMismatch {
+                    offset,
+                    expected,
+                    found: depth,
+                })
+            }
+            None => depths[offset] = Some(depth),
+        }
+
+        let instruction = instructions[offset].as_ref().unwrap();
+        let (pops, pushes) = instruction.stack_effect();
+        if depth < pops {
+            return Err(VerifyError::StackUnderflow {
+                offset,
+                opcode: instruction.opcode,
+                depth,
+                needed: pops,
+            });
+        }
+        if let Some(slot) = local_slot(instruction) {
+            if slot >= depth {
+                return Err(VerifyError::SlotOutOfRange {
+                    offset,
+                    slot,
+                    depth,
+                });
+            }
+        }
+        // An instruction may raise after popping its operands, so the
+        // handler must not keep more than what is left.
+        for (index, handler) in chunk.handlers.iter().enumerate() {
+            if (handler.start..handler.end).contains(&offset) && depth - pops < handler.depth {
+                return Err(VerifyError::HandlerDepth {
+                    offset,
+                    index,
+                    depth: depth - pops,
+                    needed: handler.depth,
+                });
+            }
+        }
+        let depth = depth - pops + pushes;
+
+        for target in successors(instruction)? {
+            if target >= chunk.code.len() {
+                return Err(if target == instruction.next() {
+                    VerifyError::FallsOffEnd { offset }
+                } else {
+                    VerifyError::JumpOutOfRange { offset, target }
+                });
+            }
+            if instructions[target].is_none() {
+                return Err(VerifyError::JumpIntoInstruction { offset, target });
+            }
+            worklist.push((target, depth));
+        }
+    }
+
+    Ok(())
+}
+
+fn successors(instruction: &Instruction) -> Result<Vec<usize>, VerifyError> {
+    let target = || {
+        instruction.jump_target().ok_or(VerifyError::JumpBeforeStart {
+            offset: instruction.offset,
+        })
+    };
+    Ok(match instruction.opcode.info().flow {
+        Flow::Return | Flow::Throw => vec![],
+        Flow::Next => vec![instruction.next()],
+        Flow::Jump => vec![target()?],
+        Flow::Branch => vec![instruction.next(), target()?],
+    })
+}
+
+// Local slots are counted from the bottom of the stack, so a slot must
+// already hold a value when the instruction runs.
+fn local_slot(instruction: &Instruction) -> Option<usize> {
+    let operands = instruction.opcode.info().operands;
+    let index = operands.iter().position(|&o| o == Operand::Slot)?;
+    Some(instruction.operands[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(code: &[u8]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for &byte in code {
+            chunk.write(byte, 1);
+        }
+        chunk
+    }
+
+    fn op(opcode: OpCode) -> u8 {
+        opcode.into()
+    }
+
+    #[test]
+    fn accepts_well_formed_chunk() {
+        let mut chunk = chunk(&[
+            op(OpCode::OpConstant),
+            0,
+            op(OpCode::OpPrint),
+            op(OpCode::OpReturn),
+        ]);
+        chunk.constants.write(Value::Int(1));
+        assert!(verify(&chunk).is_ok());
+    }
+
+    #[test]
+    fn empty_chunk() {
+        assert!(matches!(
+            verify(&Chunk::new()),
+            Err(VerifyError::EmptyChunk)
+        ));
+    }
+
+    #[test]
+    fn line_table_mismatch() {
+        let mut chunk = chunk(&[op(OpCode::OpNil), op(OpCode::OpReturn)]);
+        chunk.lines.pop();
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::LineTableMismatch { code: 2, lines: 1 })
+        ));
+    }
+
+    #[test]
+    fn unknown_opcode() {
+        assert!(matches!(
+            verify(&chunk(&[op(OpCode::OpNil), 255])),
+            Err(VerifyError::UnknownOpcode {
+                offset: 1,
+                byte: 255
+            })
+        ));
+    }
+
+    #[test]
+    fn truncated_operand() {
+        assert!(matches!(
+            verify(&chunk(&[op(OpCode::OpJump), 0])),
+            Err(VerifyError::TruncatedOperand {
+                offset: 0,
+                opcode: OpCode::OpJump
+            })
+        ));
+    }
+
+    #[test]
+    fn constant_out_of_range() {
+        let chunk = chunk(&[
+            op(OpCode::OpConstant),
+            3,
+            op(OpCode::OpPop),
+            op(OpCode::OpReturn),
+        ]);
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::ConstantOutOfRange {
+                offset: 0,
+                index: 3,
+                count: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn constant_type() {
+        let mut chunk = chunk(&[
+            op(OpCode::OpGetGlobal),
+            0,
+            op(OpCode::OpPop),
+            op(OpCode::OpReturn),
+        ]);
+        chunk.constants.write(Value::Int(1));
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::ConstantType {
+                offset: 0,
+                opcode: OpCode::OpGetGlobal,
+                expected: "string",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn jump_out_of_range() {
+        assert!(matches!(
+            verify(&chunk(&[op(OpCode::OpJump), 0, 10, op(OpCode::OpReturn)])),
+            Err(VerifyError::JumpOutOfRange {
+                offset: 0,
+                target: 13
+            })
+        ));
+    }
+
+    #[test]
+    fn jump_into_instruction() {
+        let mut chunk = chunk(&[
+            op(OpCode::OpJump),
+            0,
+            1,
+            op(OpCode::OpConstant),
+            0,
+            op(OpCode::OpPop),
+            op(OpCode::OpReturn),
+        ]);
+        chunk.constants.write(Value::Nil);
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::JumpIntoInstruction {
+                offset: 0,
+                target: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn jump_before_start() {
+        assert!(matches!(
+            verify(&chunk(&[op(OpCode::OpLoop), 0, 10, op(OpCode::OpReturn)])),
+            Err(VerifyError::JumpBeforeStart { offset: 0 })
+        ));
+    }
+
+    #[test]
+    fn slot_out_of_range() {
+        let chunk = chunk(&[
+            op(OpCode::OpGetLocal),
+            0,
+            op(OpCode::OpPop),
+            op(OpCode::OpReturn),
+        ]);
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::SlotOutOfRange {
+                offset: 0,
+                slot: 0,
+                depth: 0
+            })
+        ));
+    }
+
+    #[test]
+    fn stack_underflow() {
+        assert!(matches!(
+            verify(&chunk(&[
+                op(OpCode::OpNil),
+                op(OpCode::OpAdd),
+                op(OpCode::OpReturn)
+            ])),
+            Err(VerifyError::StackUnderflow {
+                offset: 1,
+                opcode: OpCode::OpAdd,
+                depth: 1,
+                needed: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn stack_mismatch() {
+        // The false branch skips the OpNil, so OpReturn is reached with
+        // one value on one path and none on the other.
+        let chunk = chunk(&[
+            op(OpCode::OpTrue),
+            op(OpCode::OpJumpIfFalse),
+            0,
+            1,
+            op(OpCode::OpNil),
+            op(OpCode::OpReturn),
+        ]);
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::StackMismatch { offset: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn falls_off_end() {
+        assert!(matches!(
+            verify(&chunk(&[op(OpCode::OpNil)])),
+            Err(VerifyError::FallsOffEnd { offset: 0 })
+        ));
+    }
+
+    #[test]
+    fn handler_out_of_range() {
+        let mut chunk = chunk(&[op(OpCode::OpReturn)]);
+        chunk.handlers.push(Handler {
+            start: 0,
+            end: 5,
+            target: 0,
+            depth: 0,
+        });
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::HandlerOutOfRange { index: 0 })
+        ));
+    }
+
+    #[test]
+    fn handler_into_instruction() {
+        let mut chunk = chunk(&[
+            op(OpCode::OpConstant),
+            0,
+            op(OpCode::OpPop),
+            op(OpCode::OpReturn),
+        ]);
+        chunk.constants.write(Value::Nil);
+        chunk.handlers.push(Handler {
+            start: 0,
+            end: 2,
+            target: 1,
+            depth: 0,
+        });
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::HandlerIntoInstruction {
+                index: 0,
+                offset: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn handler_depth() {
+        let mut chunk = chunk(&[op(OpCode::OpTrue), op(OpCode::OpPop), op(OpCode::OpReturn)]);
+        chunk.handlers.push(Handler {
+            start: 0,
+            end: 2,
+            target: 2,
+            depth: 1,
+        });
+        assert!(matches!(
+            verify(&chunk),
+            Err(VerifyError::HandlerDepth {
+                offset: 0,
+                index: 0,
+                depth: 0,
+                needed: 1
+            })
+        ));
+    }
+}
@@ -1,13 +1,20 @@
-use crate::chunk;
+use crate::builtins;
 use crate::chunk::*;
 use crate::compiler::*;
+use crate::error::*;
+use crate::map::Map;
 use crate::value::*;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 pub struct VM {
     chunk: Chunk,
     ip: usize,
     stack: Vec<Value>,
     globals: HashMap<String, Value>,
+    // Globals declared with `const`. The compiler rejects assignments it
+    // can see; this catches those from later REPL lines.
+    constants: HashSet<String>,
 }
 
 pub enum InterpretResult {
@@ -16,21 +23,45 @@ pub enum InterpretResult {
     RuntimeError,
 }
 
-macro_rules! BinaryOp {
-    ($self:ident, $op:tt) => {
-        if !($self.peek(0).is_number() && $self.peek(1).is_number()) {
-            $self.runtime_error("Operands must be two numbers.");
-            return InterpretResult::RuntimeError;
+// Raises a RuntimeError, continuing at its handler if one catches it and
+// otherwise ending the run.
+macro_rules! throw {
+    ($self:ident, $error:expr) => {
+        let error = $self.error_object($error);
+        if $self.throw(error) {
+            continue;
         }
+        return InterpretResult::RuntimeError;
+    };
+}
 
+// Takes an operator, or a function of two values such as `Value::pow`.
+macro_rules! BinaryOp {
+    ($self:ident, $op:tt) => {
+        BinaryOp!($self, |a: Value, b: Value| a $op b)
+    };
+    ($self:ident, $operation:expr) => {
         let b = $self.stack.pop().unwrap();
         let a = $self.stack.pop().unwrap();
-        $self.stack.push(a $op b);
+        match ($operation)(a, b) {
+            Ok(value) => $self.stack.push(value),
+            Err(error) => {
+                throw!($self, error);
+            }
+        }
     };
 }
 
+// Numbers compare numerically, ints against floats exactly, and strings by code point, which for UTF-8
+// is the same as comparing their bytes.
 macro_rules! BinaryCompOp {
     ($self:ident, $op:tt) => {
+        let comparable = ($self.peek(0).is_number() && $self.peek(1).is_number())
+            || ($self.peek(0).is_string() && $self.peek(1).is_string());
+        if !comparable {
+            throw!($self, type_error("Operands must be two numbers or two strings."));
+        }
+
         let b = $self.stack.pop().unwrap();
         let a = $self.stack.pop().unwrap();
         $self.stack.push(Value::Boolean(a $op b));
@@ -44,6 +75,7 @@ impl VM {
             ip: 0,
             stack: Vec::new(),
             globals: HashMap::new(),
+            constants: HashSet::new(),
         }
     }
 
@@ -51,66 +83,88 @@ impl VM {
         self.chunk.free();
         self.stack.clear();
         self.globals.clear();
+        self.constants.clear();
     }
 
     pub fn interpret(&mut self, source: String) -> InterpretResult {
         let mut chunk = Chunk::new();
         let mut compiler = Compiler::new(&mut chunk);
-        compiler.compile(source);
+        if compiler.compile(source) {
+            return InterpretResult::CompileError;
+        }
+        self.interpret_chunk(chunk)
+    }
+
+    pub fn interpret_chunk(&mut self, chunk: Chunk) -> InterpretResult {
+        self.chunk = chunk;
         self.ip = 0;
-        self.run(&chunk)
+        self.run()
     }
 
     fn peek(&self, distance: usize) -> Value {
         self.stack[self.stack.len() - 1 - distance].clone()
     }
 
-    fn run(&mut self, chunk: &Chunk) -> InterpretResult {
+    fn run(&mut self) -> InterpretResult {
         loop {
+            #[cfg(feature = "debug_trace_execution")]
             {
                 print!("          ");
                 for slot in &self.stack {
                     print!("[ {} ]", slot);
                 }
                 println!();
-                chunk.disassemble_instruction(self.ip);
+                self.chunk.disassemble_instruction(self.ip);
             }
 
-            let instruction = self.read_byte(chunk);
+            let instruction = match OpCode::try_from(self.read_byte()) {
+                Ok(instruction) => instruction,
+                Err(byte) => return self.malformed(&format!("Unknown opcode {}.", byte)),
+            };
             match instruction {
-                OpCode::OpDefineGlobal => {
-                    let constant = self.read_constant().clone();
-                    if let Value::Str(s) = constant {
-                        let p = self.stack.pop().unwrap();
-                        println!("Inserting {} into globals {}", s, p);
-                        self.globals.insert(s, p.clone());
-                    } else {
-                        panic!("Unable to read constant from table");
+                OpCode::OpDefineGlobal | OpCode::OpDefineConstant => {
+                    let Some(s) = self.read_name() else {
+                        return self.malformed("Variable name is not a string constant.");
+                    };
+                    if self.constants.contains(&s) {
+                        throw!(
+                            self,
+                            name_error(format!("Constant '{}' is already defined.", s))
+                        );
                     }
+                    if instruction == OpCode::OpDefineConstant {
+                        self.constants.insert(s.clone());
+                    }
+                    let p = self.stack.pop().unwrap();
+                    self.globals.insert(s, p);
                 }
 
                 OpCode::OpGetGlobal => {
-                    let constant = self.read_constant().clone();
-                    if let Value::Str(s) = constant {
-                        if let Some(v) = self.globals.get(&s) {
-                            self.stack.push(v.clone())
-                        } else {
-                            return InterpretResult::RuntimeError;
-                        }
+                    let Some(s) = self.read_name() else {
+                        return self.malformed("Variable name is not a string constant.");
+                    };
+                    if let Some(v) = self.globals.get(&s) {
+                        self.stack.push(v.clone())
                     } else {
-                        panic!("Unable to read constant from table");
+                        throw!(self, name_error(format!("Undefined variable '{}'.", s)));
                     }
                 }
 
                 OpCode::OpSetGlobal => {
-                    let constant = self.read_constant().clone();
-                    if let Value::Str(s) = constant {
-                        let p = self.stack.pop().unwrap();
-                        println!("Inserting {} into globals {}", s, p);
-                        self.globals.insert(s, p.clone());
-                    } else {
-                        panic!("Unable to read constant from table");
+                    let Some(s) = self.read_name() else {
+                        return self.malformed("Variable name is not a string constant.");
+                    };
+                    if !self.globals.contains_key(&s) {
+                        throw!(self, name_error(format!("Undefined variable '{}'.", s)));
+                    }
+                    if self.constants.contains(&s) {
+                        throw!(
+                            self,
+                            name_error(format!("Cannot assign to constant '{}'.", s))
+                        );
                     }
+                    let p = self.peek(0);
+                    self.globals.insert(s, p);
                 }
                 
                 OpCode::OpReturn => {
@@ -121,13 +175,13 @@ impl VM {
                     self.stack.push(constant);
                 }
                 OpCode::OpNegate => {
-                    if !self.peek(0).is_number() {
-                        self.runtime_error("Operand must be a number.");
-                        return InterpretResult::RuntimeError;
-                    }
-
                     let value = self.stack.pop().unwrap();
-                    self.stack.push(-value);
+                    match -value {
+                        Ok(value) => self.stack.push(value),
+                        Err(error) => {
+                            throw!(self, error);
+                        }
+                    }
                 }
                 OpCode::OpAdd => {
                     if self.peek(0).is_string() && self.peek(1).is_string() {
@@ -153,6 +207,192 @@ impl VM {
                     BinaryOp!(self, /);
                 }
 
+                OpCode::OpModulo => {
+                    BinaryOp!(self, %);
+                }
+
+                OpCode::OpFloorDivide => {
+                    BinaryOp!(self, Value::floor_div);
+                }
+
+                OpCode::OpPower => {
+                    BinaryOp!(self, Value::pow);
+                }
+
+                OpCode::OpBitAnd => {
+                    BinaryOp!(self, &);
+                }
+
+                OpCode::OpBitOr => {
+                    BinaryOp!(self, |);
+                }
+
+                OpCode::OpBitXor => {
+                    BinaryOp!(self, ^);
+                }
+
+                OpCode::OpBitNot => {
+                    let value = self.stack.pop().unwrap();
+                    match value.bit_not() {
+                        Ok(value) => self.stack.push(value),
+                        Err(error) => {
+                            throw!(self, error);
+                        }
+                    }
+                }
+
+                OpCode::OpShiftLeft => {
+                    BinaryOp!(self, <<);
+                }
+
+                OpCode::OpShiftRight => {
+                    BinaryOp!(self, >>);
+                }
+
+                OpCode::OpBuildList => {
+                    let count = self.read_byte() as usize;
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    self.stack.push(Value::list(items));
+                }
+
+                OpCode::OpBuildMap => {
+                    let count = self.read_byte() as usize;
+                    let entries = self.stack.split_off(self.stack.len() - count * 2);
+                    let mut map = Map::new();
+                    // Not a `for` loop, where throw!'s `continue` would
+                    // carry on with the next entry.
+                    let inserted = entries
+                        .chunks(2)
+                        .try_for_each(|pair| map.insert(pair[0].clone(), pair[1].clone()));
+                    match inserted {
+                        Ok(()) => self.stack.push(Value::map(map)),
+                        Err(error) => {
+                            throw!(self, error);
+                        }
+                    }
+                }
+
+                OpCode::OpIndexGet => {
+                    let index = self.stack.pop().unwrap();
+                    let object = self.stack.pop().unwrap();
+                    match builtins::index_get(&object, &index) {
+                        Ok(value) => self.stack.push(value),
+                        Err(error) => {
+                            throw!(self, error);
+                        }
+                    }
+                }
+
+                OpCode::OpIndexSet => {
+                    let value = self.stack.pop().unwrap();
+                    let index = self.stack.pop().unwrap();
+                    let object = self.stack.pop().unwrap();
+                    if let Err(error) = builtins::index_set(&object, &index, value.clone()) {
+                        throw!(self, error);
+                    }
+                    self.stack.push(value);
+                }
+
+                OpCode::OpDupTwo => {
+                    let a = self.peek(1);
+                    let b = self.peek(0);
+                    self.stack.push(a);
+                    self.stack.push(b);
+                }
+
+                OpCode::OpTuck => {
+                    let top = self.peek(0);
+                    let at = self.stack.len() - 3;
+                    self.stack.insert(at, top);
+                }
+
+                OpCode::OpInvoke => {
+                    let Some(name) = self.read_name() else {
+                        return self.malformed("Method name is not a string constant.");
+                    };
+                    let count = self.read_byte() as usize;
+                    let args = self.stack.split_off(self.stack.len() - count);
+                    let receiver = self.stack.pop().unwrap();
+                    match builtins::invoke(&receiver, &name, &args) {
+                        Ok(value) => self.stack.push(value),
+                        Err(error) => {
+                            throw!(self, error);
+                        }
+                    }
+                }
+
+                OpCode::OpGetLocal => {
+                    let slot = self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+
+                OpCode::OpSetLocal => {
+                    let slot = self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+
+                OpCode::OpRange | OpCode::OpRangeInclusive => {
+                    let inclusive = instruction == OpCode::OpRangeInclusive;
+                    BinaryOp!(self, |a, b| builtins::range(&a, &b, inclusive));
+                }
+
+                OpCode::OpGetIter => {
+                    let value = self.stack.pop().unwrap();
+                    match builtins::iterate(&value) {
+                        Ok(iter) => self.stack.push(Value::Iterator(Rc::new(RefCell::new(iter)))),
+                        Err(error) => {
+                            throw!(self, error);
+                        }
+                    }
+                }
+
+                OpCode::OpForIter => {
+                    let offset = self.read_short() as usize;
+                    let Value::Iterator(iter) = self.peek(0) else {
+                        return self.malformed("For-in over a non-iterator.");
+                    };
+                    let next = iter.borrow_mut().next();
+                    match next {
+                        Some(value) => self.stack.push(value),
+                        None => {
+                            self.stack.push(Value::Nil);
+                            self.ip += offset;
+                        }
+                    }
+                }
+
+                OpCode::OpJump => {
+                    let offset = self.read_short() as usize;
+                    self.ip += offset;
+                }
+
+                OpCode::OpJumpIfFalse => {
+                    let offset = self.read_short() as usize;
+                    if self.stack.pop().unwrap().is_falsey() {
+                        self.ip += offset;
+                    }
+                }
+
+                OpCode::OpJumpIfNil | OpCode::OpJumpIfNotNil => {
+                    let offset = self.read_short() as usize;
+                    let is_nil = matches!(self.stack.last(), Some(Value::Nil));
+                    if is_nil == (instruction == OpCode::OpJumpIfNil) {
+                        self.ip += offset;
+                    }
+                }
+
+                OpCode::OpMatchList => {
+                    let count = self.read_byte() as usize;
+                    let value = self.stack.pop().unwrap();
+                    let matched = matches!(value, Value::List(list) if list.borrow().len() == count);
+                    self.stack.push(Value::Boolean(matched));
+                }
+
+                OpCode::OpLoop => {
+                    let offset = self.read_short() as usize;
+                    self.ip -= offset;
+                }
+
                 OpCode::OpNil => self.stack.push(Value::Nil),
 
                 OpCode::OpTrue => self.stack.push(Value::Boolean(true)),
@@ -185,18 +425,63 @@ impl VM {
                 OpCode::OpPop => {
                     self.stack.pop();
                 }
+
+                OpCode::OpThrow => {
+                    let error = match self.stack.pop().unwrap() {
+                        // Rethrowing keeps the original trace.
+                        Value::Error(error) => error,
+                        Value::Str(message) => {
+                            self.error_object(RuntimeError::new(ErrorKind::Thrown, message))
+                        }
+                        // Anything else would be lost in a message string.
+                        _ => self
+                            .error_object(type_error("Thrown value must be an error or a string.")),
+                    };
+                    if self.throw(error) {
+                        continue;
+                    }
+                    return InterpretResult::RuntimeError;
+                }
+
+                OpCode::OpEndFinally => {
+                    if let Value::Error(error) = self.stack.pop().unwrap() {
+                        if self.throw(error) {
+                            continue;
+                        }
+                        return InterpretResult::RuntimeError;
+                    }
+                }
+
+                OpCode::OpBuildString => {
+                    let count = self.read_byte() as usize;
+                    let parts = self.stack.split_off(self.stack.len() - count);
+                    let string: String = parts.iter().map(|part| part.to_string()).collect();
+                    self.stack.push(Value::Str(string));
+                }
             }
         }
     }
 
-    fn read_string(&mut self, chunk: &Chunk) -> String {
-        self.read_constant().to_string()
+    fn read_byte(&mut self) -> u8 {
+        let value = self.chunk.read(self.ip);
+        self.ip += 1;
+        value
     }
 
-    fn read_byte(&mut self, chunk: &Chunk) -> OpCode {
-        let value = chunk.read(self.ip);
-        self.ip += 1;
-        value.into()
+    fn read_short(&mut self) -> u16 {
+        let high = self.read_byte() as u16;
+        let low = self.read_byte() as u16;
+        high << 8 | low
+    }
+
+    // A Name operand. None if it is not a string constant, which the
+    // verifier rules out for loaded chunks.
+    fn read_name(&mut self) -> Option<String> {
+        let index = self.read_byte() as usize;
+        match self.chunk.constants.values.get(index) {
+            Some(Value::Str(name)) => Some(name.clone()),
+            _ => None,
+        }
     }
 
     fn read_constant(&mut self) -> Value {
@@ -205,12 +490,657 @@ impl VM {
         self.chunk.get_constant(value)
     }
 
-    fn runtime_error(&mut self, message: &str) {
-        println!("{}", message);
-        let instruction = self.ip - 1;
-        let line = self.chunk.lines[instruction];
-        println!("[line {}] in script", line);
+    // An error raised by the instruction just read.
+    fn error_object(&self, error: RuntimeError) -> Rc<ErrorObject> {
+        let line = self.chunk.lines[self.ip - 1];
+        Rc::new(ErrorObject {
+            kind: error.kind,
+            message: error.message,
+            trace: vec![format!("[line {}] in script", line)],
+        })
+    }
+
+    // Unwinds to the handler covering the current instruction, returning
+    // false if there is none and the error has been reported instead.
+    fn throw(&mut self, error: Rc<ErrorObject>) -> bool {
+        match self.chunk.handler(self.ip - 1).copied() {
+            Some(handler) => {
+                self.stack.truncate(handler.depth);
+                self.stack.push(Value::Error(error));
+                self.ip = handler.target;
+                true
+            }
+            None => {
+                self.report(&error);
+                false
+            }
+        }
+    }
+
+    // Ends the run on a chunk the VM cannot execute. Unlike a runtime
+    // error, scripts cannot catch this.
+    fn malformed(&mut self, message: &str) -> InterpretResult {
+        let error = self.error_object(RuntimeError::new(ErrorKind::Value, message));
+        self.report(&error);
+        InterpretResult::RuntimeError
+    }
+
+    fn report(&mut self, error: &ErrorObject) {
+        println!("{}", error.message);
+        for line in &error.trace {
+            println!("{}", line);
+        }
 
         self.stack.clear();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &[u8]) -> InterpretResult {
+        let mut chunk = Chunk::new();
+        for &byte in code {
+            chunk.write(byte, 1);
+        }
+        VM::new().interpret_chunk(chunk)
+    }
+
+    fn op(opcode: OpCode) -> u8 {
+        opcode.into()
+    }
+
+    #[test]
+    fn reports_for_in_over_a_non_iterator() {
+        let code = [
+            op(OpCode::OpNil),
+            op(OpCode::OpForIter),
+            0,
+            0,
+            op(OpCode::OpReturn),
+        ];
+        assert!(matches!(run(&code), InterpretResult::RuntimeError));
+    }
+
+    // Runs a script that appends to a global `log` string, returning it.
+    fn log(source: &str) -> String {
+        let mut chunk = Chunk::new();
+        assert!(!Compiler::new(&mut chunk).compile(format!("var log = \"\";\n{}", source)));
+        assert!(crate::verifier::verify(&chunk).is_ok());
+        let mut vm = VM::new();
+        assert!(matches!(vm.interpret_chunk(chunk), InterpretResult::Ok));
+        match vm.globals.get("log") {
+            Some(Value::Str(log)) => log.clone(),
+            other => panic!("log is {:?}", other),
+        }
+    }
+
+    #[test]
+    fn break_and_continue_run_finally_blocks() {
+        let source = r#"
+            for i in 0..5 {
+                try {
+                    match i {
+                        1 => continue;
+                        3 => break;
+                        _ => log += "body${i} ";
+                    }
+                } finally {
+                    log += "finally${i} ";
+                }
+            }
+            log += "after";
+        "#;
+        assert_eq!(
+            log(source),
+            "body0 finally0 finally1 body2 finally2 finally3 after"
+        );
+    }
+
+    #[test]
+    fn exits_run_every_finally_block_they_leave() {
+        let source = r#"
+            outer: for i in 0..2 {
+                var kept = "k${i}";
+                for j in 0..3 {
+                    try {
+                        try {
+                            var t = j;
+                            match j { 1 => continue outer; _ => log += "${i}${j} "; }
+                        } finally {
+                            var inner = "f1 ";
+                            log += inner;
+                        }
+                    } catch (e) {
+                        log += "never ";
+                    } finally {
+                        log += "f2 ";
+                    }
+                }
+                log += "unreached ";
+            }
+            log += "after";
+        "#;
+        assert_eq!(log(source), "00 f1 f2 f1 f2 10 f1 f2 f1 f2 after");
+    }
+
+    #[test]
+    fn exits_leave_catch_blocks() {
+        let source = r#"
+            for i in 0..3 {
+                var x = i;
+                try {
+                    var y = "y";
+                    match x { 1 => break; _ => log += y; }
+                } catch (e) {}
+                log += "${x} ";
+            }
+            for i in 0..3 {
+                var k = "k";
+                try {
+                    throw "boom";
+                } catch (e) {
+                    var z = 1;
+                    match i { 1 => break; _ => log += e.message(); }
+                } finally {
+                    log += " cleanup ${k}";
+                }
+            }
+        "#;
+        assert_eq!(log(source), "y0 boom cleanup k cleanup k");
+    }
+
+    #[test]
+    fn errors_in_finally_replace_exits() {
+        let source = r#"
+            try {
+                for i in 0..3 {
+                    try {
+                        break;
+                    } finally {
+                        throw "from finally";
+                    }
+                }
+                log += "unreached";
+            } catch (e) {
+                log += e.message();
+            }
+            for i in 0..3 {
+                try {
+                    throw "lost";
+                } finally {
+                    continue;
+                }
+            }
+            log += " after";
+        "#;
+        assert_eq!(log(source), "from finally after");
+    }
+
+    #[test]
+    fn floor_division_rounds_down() {
+        let source = r#"
+            var a = 7;
+            log += "${a // 2} ${-7 // 2} ${7 // -2} ${7.5 // 2} ${-7.5 // 2}";
+            a //= 2; // a comment after the statement
+            var b = a
+                // a comment line inside the expression
+                + 1;
+            log += " ${a} ${b}";
+        "#;
+        assert_eq!(log(source), "3 -4 -4 3 -4 3 4");
+    }
+
+    #[test]
+    fn postfix_increment_of_an_element_leaves_the_old_value() {
+        let source = r#"
+            var a = [0.1, 1];
+            var m = {"k": 0.1};
+            var i = 0;
+            log += "${a[0]++} ${a[0]} ${m["k"]--} ${m["k"]} ";
+            log += "${a[i++]--} ${a} ${i}";
+        "#;
+        assert_eq!(
+            log(source),
+            "0.1 1.1 0.1 -0.9 1.1 [0.10000000000000009, 1] 1"
+        );
+    }
+
+    // Runs each statement in its own try block, logging "kind: message"
+    // for the error it raises, one per line.
+    fn errors(statements: &[&str]) -> String {
+        let source: String = statements
+            .iter()
+            .map(|statement| {
+                format!(
+                    "try {{ {} log += \"none\\n\"; }} catch (e) {{ log += \"${{e.kind()}}: ${{e.message()}}\\n\"; }}\n",
+                    statement
+                )
+            })
+            .collect();
+        log(&source)
+    }
+
+    #[test]
+    fn builds_and_indexes_lists() {
+        let source = r#"
+            var empty = [];
+            var xs = [1, "two", [3], nil,];
+            log += "${empty} ${xs} ${xs[0]} ${xs[-1]} ${xs[-4]} ${xs[2][0]} ";
+            xs[1] = 2;
+            xs[-1] = 4;
+            xs[2][0] += 30;
+            log += "${xs}";
+        "#;
+        assert_eq!(
+            log(source),
+            "[] [1, \"two\", [3], nil] 1 nil 1 3 [1, 2, [33], 4]"
+        );
+    }
+
+    #[test]
+    fn reports_bad_list_indexes() {
+        let statements = [
+            "[1, 2][2];",
+            "[1, 2][-3];",
+            "[][0];",
+            "var a = [1]; a[1] = 2;",
+            "[1][1.0];",
+            "[1][\"0\"];",
+            "nil[0];",
+            "\"ab\"[0] = \"c\";",
+        ];
+        assert_eq!(
+            errors(&statements),
+            "IndexError: List index 2 out of range for length 2.\n\
+             IndexError: List index -3 out of range for length 2.\n\
+             IndexError: List index 0 out of range for length 0.\n\
+             IndexError: List index 1 out of range for length 1.\n\
+             TypeError: List index must be an integer.\n\
+             TypeError: List index must be an integer.\n\
+             TypeError: Only lists, maps and strings can be indexed.\n\
+             TypeError: Strings cannot be modified.\n"
+        );
+    }
+
+    #[test]
+    fn calls_list_methods() {
+        // Interpolation formats its parts only once all are evaluated, so
+        // each part that mutates the list gets its own statement.
+        let source = r#"
+            var xs = [3, 1, 2];
+            xs.push(5);
+            log += "${xs.len()} ";
+            log += "${xs.pop()} ${xs} ";
+            xs.insert(0, 0);
+            // Insertion positions run to the length, so -1 appends.
+            xs.insert(-1, 9);
+            xs.insert(xs.len(), 7);
+            log += "${xs} ";
+            log += "${xs.remove(-2)} ";
+            log += "${xs.remove(0)} ${xs} ";
+            log += "${xs.slice(1)} ${xs.slice(-2)} ${xs.slice(1, -1)} ${xs.slice(3, 1)} ${xs.slice(-9, 9)} ";
+            xs.sort();
+            var words = ["b", "a", "c"];
+            words.sort();
+            log += "${xs} ${words}";
+        "#;
+        assert_eq!(
+            log(source),
+            "4 5 [3, 1, 2] [0, 3, 1, 2, 9, 7] 9 0 [3, 1, 2, 7] \
+             [1, 2, 7] [2, 7] [1, 2] [] [3, 1, 2, 7] [1, 2, 3, 7] [\"a\", \"b\", \"c\"]"
+        );
+    }
+
+    #[test]
+    fn reports_bad_list_method_calls() {
+        let statements = [
+            "[].pop();",
+            "[1].push();",
+            "[1].slice();",
+            "[1].insert(2, 0);",
+            "[1].remove(1);",
+            "[1, \"a\"].sort();",
+            "[1].shuffle();",
+        ];
+        assert_eq!(
+            errors(&statements),
+            "IndexError: Cannot pop from an empty list.\n\
+             TypeError: push() expects 1 argument but got 0.\n\
+             TypeError: slice() expects 1 to 2 arguments but got 0.\n\
+             IndexError: List index 2 out of range for length 2.\n\
+             IndexError: List index 1 out of range for length 1.\n\
+             TypeError: List elements must be all numbers or all strings to sort.\n\
+             TypeError: Undefined method 'shuffle' for list.\n"
+        );
+    }
+
+    #[test]
+    fn builds_and_indexes_maps() {
+        let source = r#"
+            var empty = {};
+            var m = {"a": 1, 2: "two", nil: [3],};
+            log += "${empty} ${m["a"]} ${m[2]} ${m[nil][0]} ";
+            m["a"] = 10;
+            m["b"] = 20;
+            m[nil][0] += 1;
+            log += "${m} ";
+            // Overwriting keeps a key's place; removing shifts the rest.
+            m.remove(2);
+            m[2] = "again";
+            log += "${m}";
+        "#;
+        assert_eq!(
+            log(source),
+            "{} 1 two 3 {\"a\": 10, 2: \"two\", nil: [4], \"b\": 20} \
+             {\"a\": 10, nil: [4], \"b\": 20, 2: \"again\"}"
+        );
+    }
+
+    #[test]
+    fn treats_equal_numbers_as_one_key() {
+        let source = r#"
+            var m = {1: "int"};
+            m[1.0] = "float";
+            m[2 ** 70] = "big";
+            m[0.5] = "half";
+            log += "${m.len()} ${m[1]} ${m[2.0 ** 70]} ${m[1 / 2.0]} ${m.contains(1.5)} ";
+            m[1..3] = "range";
+            log += "${m[1..3]} ${m.contains(1..=2)}";
+        "#;
+        assert_eq!(log(source), "3 float big half false range false");
+    }
+
+    #[test]
+    fn calls_map_methods_and_iterates_keys() {
+        let source = r#"
+            var m = {"x": 1, "y": 2, "z": 3};
+            log += "${m.len()} ${m.keys()} ${m.values()} ${m.contains("y")} ${m.contains("w")} ";
+            log += "${m.remove("y")} ";
+            for key in m {
+                log += "${key}=${m[key]} ";
+            }
+            log += "${m.len()}";
+        "#;
+        assert_eq!(
+            log(source),
+            "3 [\"x\", \"y\", \"z\"] [1, 2, 3] true false 2 x=1 z=3 2"
+        );
+    }
+
+    #[test]
+    fn reports_bad_map_keys() {
+        let statements = [
+            "var m = {\"a\": 1}; m[\"b\"];",
+            "var m = {}; m.remove(1);",
+            "var m = {[1]: 2};",
+            "var m = 5; log += \"${m} \";",
+            "var m = {}; var k = {}; m[k] = 1;",
+            "var m = {}; m[0 / 0.0];",
+            "var m = {}; m.contains([]);",
+            "var m = {}; m.keys(1);",
+            "var m = {}; m.clear();",
+        ];
+        assert_eq!(
+            errors(&statements),
+            "KeyError: Key \"b\" not found in map.\n\
+             KeyError: Key 1 not found in map.\n\
+             TypeError: A list cannot be a map key.\n\
+             5 none\n\
+             TypeError: A map cannot be a map key.\n\
+             ValueError: NaN cannot be a map key.\n\
+             TypeError: A list cannot be a map key.\n\
+             TypeError: keys() expects 0 arguments but got 1.\n\
+             TypeError: Undefined method 'clear' for map.\n"
+        );
+    }
+
+    #[test]
+    fn applies_compound_assignments() {
+        let source = r#"
+            var g = 7;
+            g += 3;
+            g -= 4;
+            g *= 5;
+            g /= 4;
+            g %= 4;
+            log += "${g} ";
+            g **= 10;
+            g //= -3;
+            log += "${g} ";
+            g &= 12;
+            g |= 3;
+            g ^= 5;
+            g <<= 2;
+            g >>= 3;
+            log += "${g} ";
+            var s = "a";
+            s += "b";
+            log += "${s} ";
+            {
+                var x = 1.5;
+                x *= 2;
+                x -= 0.5;
+                log += "${x} ";
+            }
+            var xs = [1, [2]];
+            xs[0] += 10;
+            xs[1][0] <<= 3;
+            xs[-2] -= 1;
+            log += "${xs} ${g += 1}";
+        "#;
+        assert_eq!(log(source), "3 -19683 5 ab 2.5 [10, [16]] 6");
+    }
+
+    #[test]
+    fn increments_leave_the_new_or_old_value() {
+        let source = r#"
+            var i = 5;
+            log += "${i++} ${i} ${++i} ${i} ${i--} ${i} ${--i} ${i} ";
+            {
+                var j = 0;
+                var a = j++;
+                var b = ++j;
+                log += "${a} ${b} ${j} ";
+            }
+            var xs = [1.5, 20];
+            log += "${++xs[0]} ${xs[1]--} ";
+            log += "${xs}";
+        "#;
+        assert_eq!(log(source), "5 6 7 7 7 6 5 5 0 2 2 2.5 20 [2.5, 19]");
+    }
+
+    #[test]
+    fn evaluates_an_updated_element_once() {
+        let source = r#"
+            var xs = [10, 20, 30];
+            var k = 0;
+            xs[k++] += 1;
+            xs[k++]++;
+            ++xs[k++];
+            log += "${xs} ${k}";
+        "#;
+        assert_eq!(log(source), "[11, 21, 31] 3");
+    }
+
+    #[test]
+    fn labeled_exits_leave_outer_loops() {
+        let source = r#"
+            outer: for i in 0..3 {
+                for j in 0..3 {
+                    match j { 1 => continue outer; _ => nil; }
+                    match i { 2 => break outer; _ => nil; }
+                    var local = i * 10 + j;
+                    log += "${local} ";
+                }
+                log += "never ";
+            }
+            rows: for r in [[1, 2], [3, 4]] {
+                cols: for c in r {
+                    match c { 2 => continue rows; 4 => break cols; _ => nil; }
+                    log += "${c} ";
+                }
+                log += "row ";
+            }
+            // The exits popped the loops' locals, so this gets its own slot.
+            {
+                var after = "after";
+                log += after;
+            }
+        "#;
+        assert_eq!(log(source), "0 10 1 3 row after");
+    }
+
+    #[test]
+    fn unlabeled_exits_pop_body_locals() {
+        let source = r#"
+            for i in 0..5 {
+                var a = i;
+                match a { 1 => continue; 3 => break; _ => nil; }
+                var b = a * 2;
+                log += "${b} ";
+            }
+            {
+                var after = "after";
+                log += after;
+            }
+        "#;
+        assert_eq!(log(source), "0 4 after");
+    }
+
+    #[test]
+    fn matches_the_first_fitting_arm() {
+        let source = r#"
+            for v in [0, 1, 2, -3, "x", "y", "z", true, nil, [], [1, 2], [1, [2, 3]], [5, 5], [4, 6], 7.0, 8] {
+                match v {
+                    0 => log += "zero ";
+                    1 | 2 => log += "small ";
+                    -3 => log += "minus ";
+                    "x" | "y" => log += "xy ";
+                    true | nil => log += "${v} ";
+                    [] => log += "empty ";
+                    [1, [a, b]] => log += "nested ${a + b} ";
+                    [a, b] if a == b => log += "pair of ${a} ";
+                    [a, b] => log += "pair ${a},${b} ";
+                    n if n == 7 => log += "seven ";
+                    _ => log += "other ${v} ";
+                }
+            }
+        "#;
+        assert_eq!(
+            log(source),
+            "zero small small minus xy xy other z true nil empty pair 1,2 nested 5 \
+             pair of 5 pair 4,6 seven other 8 "
+        );
+    }
+
+    #[test]
+    fn ignores_a_value_no_arm_matches() {
+        let source = r#"
+            match [1, 2, 3] {
+                [a, b] => log += "pair ";
+                x if x == nil => log += "nil ";
+            }
+            // The match popped its hidden local and bindings.
+            {
+                var after = "after";
+                log += after;
+            }
+        "#;
+        assert_eq!(log(source), "after");
+    }
+
+    #[test]
+    fn chooses_with_conditionals() {
+        let source = r#"
+            log += "${true ? 1 : 2} ${false ? 1 : 2} ${1 < 2 ? "lt" : "ge"} ";
+            // Right-associative, and only nil and false are falsey.
+            log += "${nil ? 1 : 0 ? 3 : 4} ${false ? 1 : true ? 2 : 3} ";
+            var calls = 0;
+            var r = true ? "a" : (calls = 1);
+            log += "${r} ${calls}";
+        "#;
+        assert_eq!(log(source), "1 2 lt 3 2 a 0");
+    }
+
+    #[test]
+    fn coalesces_only_nil() {
+        let source = r#"
+            var n = nil;
+            log += "${n ?? 5} ${false ?? 5} ${0 ?? 5} ${n ?? n ?? 6} ${n ?? nil ?? false} ";
+            // `??` binds tighter than `?:`, and looser than arithmetic.
+            log += "${n ?? 1 ? "a" : "b"} ${1 ?? 2 * 3} ";
+            var calls = 0;
+            var r = 1 ?? (calls = 1);
+            log += "${r} ${calls}";
+        "#;
+        assert_eq!(log(source), "5 false 0 6 false a 1 1 0");
+    }
+
+    #[test]
+    fn optional_calls_skip_the_rest_of_a_nil_chain() {
+        let source = r#"
+            var n = nil;
+            var xs = [[1, 2]];
+            log += "${n?.len()} ${"abc"?.len()} ${n?.len().foo()} ${n?.pop()[0] ?? "none"} ";
+            log += "${xs?.pop()[1]} ";
+            log += "${xs?.len() + 1}";
+        "#;
+        assert_eq!(log(source), "nil 3 nil none 2 1");
+    }
+
+    #[test]
+    fn rejects_global_constant_changes_from_later_lines() {
+        // Like REPL lines: each is compiled on its own, so the compiler
+        // cannot see the `const` and the VM has to refuse.
+        let mut vm = VM::new();
+        for line in [
+            "const X = 1; var log = \"\";",
+            "try { X = 2; } catch (e) { log += \"${e.kind()}: ${e.message()}\"; }",
+        ] {
+            assert!(
+                matches!(vm.interpret(line.to_string()), InterpretResult::Ok),
+                "{}",
+                line
+            );
+        }
+        for line in ["var X = 3;", "const X = 4;"] {
+            assert!(
+                matches!(
+                    vm.interpret(line.to_string()),
+                    InterpretResult::RuntimeError
+                ),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            vm.globals.get("log").map(Value::to_string).as_deref(),
+            Some("NameError: Cannot assign to constant 'X'.")
+        );
+        assert_eq!(
+            vm.globals.get("X").map(Value::to_string).as_deref(),
+            Some("1")
+        );
+    }
+
+    #[test]
+    fn throws_only_errors_and_messages() {
+        let statements = [
+            "throw \"plain\";",
+            "try { [][0]; } catch (e) { throw e; }",
+            "throw [1, 2];",
+            "throw nil;",
+            "var m = {\"code\": 1}; throw m;",
+        ];
+        assert_eq!(
+            errors(&statements),
+            "Error: plain\n\
+             IndexError: List index 0 out of range for length 0.\n\
+             TypeError: Thrown value must be an error or a string.\n\
+             TypeError: Thrown value must be an error or a string.\n\
+             TypeError: Thrown value must be an error or a string.\n"
+        );
+    }
+}